@@ -0,0 +1,159 @@
+use crate::{channel_matches, fan, resolve_hwmons, Config};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// errno for a write to a read-only filesystem.
+const EROFS: i32 = 30;
+
+/// Effective uid and every gid of this process, from /proc/self/status.
+fn credentials() -> (u32, Vec<u32>) {
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let field = |name: &str| -> Vec<u32> {
+        status
+            .lines()
+            .find_map(|l| l.strip_prefix(name))
+            .map(|v| {
+                v.split_whitespace()
+                    .filter_map(|n| n.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    // Uid/Gid lines are real, effective, saved, filesystem.
+    let uid = field("Uid:").get(1).copied().unwrap_or(u32::MAX);
+    let mut gids = field("Groups:");
+    gids.extend(field("Gid:").get(1));
+    (uid, gids)
+}
+
+/// Why `path` cannot be read (or written, with `write`), if it cannot.
+fn check_node(path: &Path, write: bool, uid: u32, gids: &[u32]) -> Option<String> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let parent_exists = path.parent().is_some_and(Path::exists);
+            return Some(if parent_exists {
+                "does not exist; the driver does not expose this channel".to_string()
+            } else {
+                "does not exist; is the driver loaded?".to_string()
+            });
+        }
+        Err(e) => return Some(e.to_string()),
+    };
+    let mode = meta.mode() & 0o777;
+    if write && mode & 0o222 == 0 {
+        return Some(format!(
+            "is read-only (mode {mode:03o}); the driver does not take writes here"
+        ));
+    }
+    let opened = if write {
+        OpenOptions::new().write(true).open(path).map(drop)
+    } else {
+        fs::File::open(path).map(drop)
+    };
+    match opened {
+        Ok(()) => None,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            let group_member = gids.contains(&meta.gid());
+            Some(format!(
+                "permission denied: owned by {}:{} mode {mode:03o}, we are uid {uid}{}",
+                meta.uid(),
+                meta.gid(),
+                if group_member {
+                    " (in its group)"
+                } else {
+                    " (not in its group)"
+                }
+            ))
+        }
+        Err(e) if e.raw_os_error() == Some(EROFS) => {
+            Some("is on a read-only filesystem".to_string())
+        }
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+/// Checks up front that every sensor can be read and every fan node
+/// written, returning one line per problem naming the path and the cause,
+/// and a hint on non-root setups when permissions were the trouble.
+/// Without this the first failure is a bare io::Error from deep inside
+/// setup, which says nothing about what to fix.
+pub fn check(cfg: &Config) -> (Vec<String>, Option<String>) {
+    let (uid, gids) = credentials();
+    let mut problems = Vec::new();
+    let mut denied = false;
+    let mut hint = None;
+    let mut report = |what: &str, path: &Path, why: String| {
+        denied |= why.starts_with("permission denied");
+        problems.push(format!("{what}: {}: {why}", path.display()));
+    };
+
+    let names: Vec<String> = cfg
+        .cpu_sensor_names
+        .iter()
+        .chain(&cfg.mem_sensor_names)
+        .cloned()
+        .collect();
+    for hw in resolve_hwmons(&names) {
+        let Ok(entries) = fs::read_dir(&hw.dir) else {
+            report(
+                "sensor",
+                Path::new(&hw.dir),
+                "cannot list directory".to_string(),
+            );
+            continue;
+        };
+        // The same inputs `max_temp_in_hwmons` reads: every temp*_input,
+        // or only the one a `chip/channel` selector picks.
+        let dir = Path::new(&hw.dir);
+        let mut inputs: Vec<_> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| {
+                let name = p.file_name().unwrap_or_default().to_string_lossy();
+                name.strip_suffix("_input").is_some_and(|prefix| {
+                    prefix.starts_with("temp")
+                        && hw
+                            .channel
+                            .as_ref()
+                            .is_none_or(|c| channel_matches(dir, prefix, c))
+                })
+            })
+            .collect();
+        inputs.sort();
+        for input in inputs {
+            if let Some(why) = check_node(&input, false, uid, &gids) {
+                report("sensor", &input, why);
+            }
+        }
+    }
+
+    for f in &cfg.fans {
+        match fan::backend_nodes(&f.backend) {
+            Ok(nodes) => {
+                for node in nodes {
+                    if let Some(why) = check_node(&node, true, uid, &gids) {
+                        report(&f.name, &node, why);
+                    }
+                }
+            }
+            Err(e) => report(
+                &f.name,
+                Path::new("backend"),
+                format!("{e}; is its driver loaded?"),
+            ),
+        }
+    }
+
+    if denied && uid != 0 {
+        hint = Some(format!(
+            "hint: running as uid {uid}. Run as root, or give a group write access with \
+             a udev rule such as\n  SUBSYSTEM==\"hwmon\", ACTION==\"add\", RUN+=\"/bin/sh -c \
+             'chgrp fan /sys%p/pwm* && chmod g+w /sys%p/pwm*'\"\nand add the service user \
+             to that group"
+        ));
+    }
+    (problems, hint)
+}
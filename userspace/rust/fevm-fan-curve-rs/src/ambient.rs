@@ -0,0 +1,118 @@
+use crate::{detect, max_temp_in_hwmons, resolve_hwmons, Config};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::time::Instant;
+
+/// How often the ambient temperature is re-estimated or re-read.
+const SCAN_SEC: f64 = 60.0;
+/// Scans a sensor must have been seen for before it can count as stable:
+/// 15 minutes at `SCAN_SEC`.
+const STABLE_SCANS: usize = 15;
+/// Largest swing over those scans for a sensor to count as stable.
+const STABLE_RANGE_C: f64 = 2.0;
+
+/// Tracks the ambient temperature: read from `ambient_sensor` (a hwmon
+/// selector, or a file holding °C or m°C that something else keeps up to
+/// date), or else estimated as the coolest hwmon temperature that has held
+/// steady for a while. The estimate sits a few degrees above the room, so
+/// `ambient_reference` should be set in the same terms.
+pub struct Ambient {
+    history: BTreeMap<String, VecDeque<f64>>,
+    last_scan: Option<Instant>,
+    celsius: Option<f64>,
+}
+
+fn read_file(path: &str) -> Result<f64, String> {
+    let raw = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let v: f64 = raw
+        .trim()
+        .parse()
+        .map_err(|_| format!("{path}: not a number: {}", raw.trim()))?;
+    // Anything hotter than this must be millidegrees, the sysfs convention.
+    Ok(if v.abs() > 200.0 { v / 1000.0 } else { v })
+}
+
+impl Ambient {
+    pub fn new() -> Self {
+        Self {
+            history: BTreeMap::new(),
+            last_scan: None,
+            celsius: None,
+        }
+    }
+
+    /// The current ambient temperature, when known.
+    pub fn celsius(&self) -> Option<f64> {
+        self.celsius
+    }
+
+    /// Refreshes the reading every `SCAN_SEC`. A failing external source
+    /// leaves the last value in place and is reported.
+    pub fn update(&mut self, cfg: &Config) -> Result<(), String> {
+        if self
+            .last_scan
+            .is_some_and(|t| t.elapsed().as_secs_f64() < SCAN_SEC)
+        {
+            return Ok(());
+        }
+        self.last_scan = Some(Instant::now());
+        match &cfg.ambient_sensor {
+            Some(path) if path.starts_with('/') => {
+                self.celsius = Some(read_file(path)?);
+            }
+            Some(name) => {
+                let hwmons = resolve_hwmons(std::slice::from_ref(name));
+                if hwmons.is_empty() {
+                    return Err(format!("ambient_sensor {name}: not found"));
+                }
+                let t = max_temp_in_hwmons(&hwmons)
+                    .map_err(|e| format!("ambient_sensor {name}: {e}"))?;
+                self.celsius = Some(t);
+            }
+            None => self.estimate(),
+        }
+        Ok(())
+    }
+
+    fn estimate(&mut self) {
+        let mut seen = Vec::new();
+        for chip in detect::chips() {
+            for temp in &chip.temps {
+                if !detect::plausible(temp.celsius) {
+                    continue;
+                }
+                let key = format!("{}/{}", chip.dir, temp.channel);
+                let samples = self.history.entry(key.clone()).or_default();
+                if samples.len() == STABLE_SCANS {
+                    samples.pop_front();
+                }
+                samples.extend(temp.celsius);
+                seen.push(key);
+            }
+        }
+        // A sensor that went away (or read garbage) starts over.
+        self.history.retain(|key, _| seen.contains(key));
+        self.celsius = self
+            .history
+            .values()
+            .filter(|s| s.len() == STABLE_SCANS)
+            .filter_map(|s| {
+                let lo = s.iter().copied().fold(f64::INFINITY, f64::min);
+                let hi = s.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                (hi - lo <= STABLE_RANGE_C).then(|| s.iter().sum::<f64>() / s.len() as f64)
+            })
+            .reduce(f64::min);
+    }
+
+    /// How far the cpu and mem curves are shifted: ambient above
+    /// `ambient_reference` moves them up by the difference, below moves
+    /// them down, clamped to `ambient_max_shift`. 0 while ambient is unknown.
+    pub fn shift(&self, cfg: &Config) -> f64 {
+        match (self.celsius, cfg.ambient_reference) {
+            (Some(t), Some(reference)) => {
+                (t - reference).clamp(-cfg.ambient_max_shift, cfg.ambient_max_shift)
+            }
+            _ => 0.0,
+        }
+    }
+}
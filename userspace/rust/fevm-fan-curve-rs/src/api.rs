@@ -0,0 +1,46 @@
+use crate::history::History;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Serves read-only queries on a Unix socket, one command per connection:
+///
+/// - `history`: the in-memory history as CSV
+///
+/// The socket is world-accessible since nothing on it changes state.
+pub fn serve(path: &str, history: Arc<Mutex<History>>) -> io::Result<()> {
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle(stream, &history) {
+                log_at!(Warning, "control socket: {e}");
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(stream: UnixStream, history: &Mutex<History>) -> io::Result<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let reply = match line.trim() {
+        "history" => history.lock().unwrap().to_csv(),
+        other => format!("error: unknown command '{other}'\n"),
+    };
+    (&stream).write_all(reply.as_bytes())
+}
+
+/// Sends one command to a running daemon and returns the reply.
+pub fn query(path: &str, command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(format!("{command}\n").as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    io::Read::read_to_string(&mut stream, &mut reply)?;
+    Ok(reply)
+}
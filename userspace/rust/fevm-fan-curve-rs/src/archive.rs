@@ -0,0 +1,315 @@
+use crate::history::Sample;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+
+const MAGIC: &[u8; 8] = b"FEVMRRD1";
+
+/// (step seconds, rows): 1s for an hour, 1min for a week, 10min for a year.
+pub const RINGS: [(u64, u64); 3] = [(1, 3_600), (60, 10_080), (600, 52_560)];
+
+/// Fixed-size round-robin archive of consolidated samples.
+///
+/// The file is a header naming the zones and fans, followed by one ring per
+/// entry in `RINGS`. A slot holds its start time (0 = never written) and one
+/// `f32` per zone temperature and fan duty, averaged over the step, plus a
+/// failsafe flag that is set if any sample in the step was in failsafe.
+/// Missing values are NaN. The size is fixed when the file is created.
+pub struct Archive {
+    file: File,
+    zones: Vec<String>,
+    fans: Vec<String>,
+    data_offset: u64,
+    acc: Vec<Acc>,
+}
+
+struct Acc {
+    start: u64,
+    sums: Vec<f64>,
+    counts: Vec<u32>,
+    failsafe: bool,
+}
+
+fn header(zones: &[String], fans: &[String]) -> Vec<u8> {
+    let names: Vec<&str> = zones.iter().chain(fans).map(String::as_str).collect();
+    let names = names.join("\n");
+    let mut out = MAGIC.to_vec();
+    out.extend((zones.len() as u32).to_le_bytes());
+    out.extend((fans.len() as u32).to_le_bytes());
+    out.extend((names.len() as u32).to_le_bytes());
+    out.extend(names.as_bytes());
+    out
+}
+
+fn slot_size(columns: usize) -> u64 {
+    8 + 4 * (columns as u64 + 1)
+}
+
+fn ring_offset(ring: usize, columns: usize) -> u64 {
+    RINGS[..ring]
+        .iter()
+        .map(|(_, rows)| rows * slot_size(columns))
+        .sum()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Archive {
+    /// Opens `path`, creating it (or starting over, if it was written for a
+    /// different set of zones and fans) as needed.
+    pub fn open(path: &str, zones: Vec<String>, fans: Vec<String>) -> io::Result<Self> {
+        let head = header(&zones, &fans);
+        let columns = zones.len() + fans.len();
+        let total = head.len() as u64 + ring_offset(RINGS.len(), columns);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut existing = vec![0u8; head.len()];
+        let reusable = file.metadata()?.len() == total
+            && file.read_exact_at(&mut existing, 0).is_ok()
+            && existing == head;
+        if !reusable {
+            if file.metadata()?.len() > 0 {
+                log_at!(Warning, "{path}: layout changed, starting a new archive");
+            }
+            file.set_len(0)?;
+            file.set_len(total)?;
+            file.write_all_at(&head, 0)?;
+        }
+
+        let acc = RINGS
+            .iter()
+            .map(|_| Acc {
+                start: 0,
+                sums: vec![0.0; columns],
+                counts: vec![0; columns],
+                failsafe: false,
+            })
+            .collect();
+        Ok(Self {
+            file,
+            zones,
+            fans,
+            data_offset: head.len() as u64,
+            acc,
+        })
+    }
+
+    pub fn record(&mut self, sample: &Sample) -> io::Result<()> {
+        let unix = sample.unix as u64;
+        let values: Vec<Option<f64>> = sample
+            .temps
+            .iter()
+            .copied()
+            .chain(sample.duties.iter().map(|d| d.map(f64::from)))
+            .collect();
+        for (ring, (step, _)) in RINGS.iter().enumerate() {
+            let start = unix - unix % step;
+            if self.acc[ring].start != start {
+                self.flush_ring(ring)?;
+                self.acc[ring].start = start;
+            }
+            let acc = &mut self.acc[ring];
+            for (i, v) in values.iter().enumerate().take(acc.sums.len()) {
+                if let Some(v) = v {
+                    acc.sums[i] += v;
+                    acc.counts[i] += 1;
+                }
+            }
+            acc.failsafe |= sample.failsafe;
+        }
+        Ok(())
+    }
+
+    /// Writes out the partially filled steps; call before exiting.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for ring in 0..RINGS.len() {
+            self.flush_ring(ring)?;
+        }
+        Ok(())
+    }
+
+    fn flush_ring(&mut self, ring: usize) -> io::Result<()> {
+        let columns = self.zones.len() + self.fans.len();
+        let acc = &mut self.acc[ring];
+        if acc.start == 0 {
+            return Ok(());
+        }
+        let (step, rows) = RINGS[ring];
+        let mut slot = acc.start.to_le_bytes().to_vec();
+        for (sum, count) in acc.sums.iter_mut().zip(&mut acc.counts) {
+            let v = if *count > 0 {
+                (*sum / f64::from(*count)) as f32
+            } else {
+                f32::NAN
+            };
+            slot.extend(v.to_le_bytes());
+            *sum = 0.0;
+            *count = 0;
+        }
+        slot.extend(f32::from(u8::from(acc.failsafe)).to_le_bytes());
+        acc.failsafe = false;
+        let offset = self.data_offset
+            + ring_offset(ring, columns)
+            + (acc.start / step % rows) * slot_size(columns);
+        self.file.write_all_at(&slot, offset)
+    }
+}
+
+/// Reads one ring back: the zone names, fan names and the consolidated
+/// samples it still holds, oldest first.
+pub fn read(path: &str, ring: usize) -> io::Result<(Vec<String>, Vec<String>, Vec<Sample>)> {
+    let file = File::open(path)?;
+    let mut fixed = [0u8; 20];
+    file.read_exact_at(&mut fixed, 0)?;
+    if &fixed[..8] != MAGIC {
+        return Err(invalid("not a fan curve archive"));
+    }
+    let word = |i: usize| u32::from_le_bytes(fixed[i..i + 4].try_into().unwrap()) as usize;
+    let (n_zones, n_fans, names_len) = (word(8), word(12), word(16));
+    let mut names = vec![0u8; names_len];
+    file.read_exact_at(&mut names, 20)?;
+    let names = String::from_utf8(names).map_err(|_| invalid("bad names"))?;
+    let names: Vec<String> = if names.is_empty() {
+        Vec::new()
+    } else {
+        names.split('\n').map(str::to_string).collect()
+    };
+    if names.len() != n_zones + n_fans {
+        return Err(invalid("bad header"));
+    }
+    let columns = names.len();
+
+    let (step, rows) = RINGS[ring];
+    let size = slot_size(columns) as usize;
+    let mut data = vec![0u8; rows as usize * size];
+    file.read_exact_at(
+        &mut data,
+        20 + names_len as u64 + ring_offset(ring, columns),
+    )?;
+
+    let f32_at = |slot: &[u8], i: usize| {
+        let at = 8 + 4 * i;
+        f32::from_le_bytes(slot[at..at + 4].try_into().unwrap())
+    };
+    let mut samples: Vec<Sample> = data
+        .chunks_exact(size)
+        .filter_map(|slot| {
+            let start = u64::from_le_bytes(slot[..8].try_into().unwrap());
+            if start == 0 {
+                return None;
+            }
+            let value = |i: usize| Some(f32_at(slot, i)).filter(|v| !v.is_nan());
+            let failsafe = f32_at(slot, columns) > 0.0;
+            Some(Sample {
+                unix: start as f64,
+                mode: if failsafe { "failsafe" } else { "normal" }.to_string(),
+                failsafe,
+                temps: (0..n_zones).map(|i| value(i).map(f64::from)).collect(),
+                duties: (n_zones..columns)
+                    .map(|i| value(i).map(|v| v.round() as i32))
+                    .collect(),
+            })
+        })
+        .collect();
+    samples.sort_by(|a, b| a.unix.total_cmp(&b.unix));
+    // Slots left over from before a long gap may be older than one lap.
+    if let Some(last) = samples.last().map(|s| s.unix) {
+        samples.retain(|s| s.unix > last - (step * rows) as f64);
+    }
+
+    let fans = names[n_zones..].to_vec();
+    let mut zones = names;
+    zones.truncate(n_zones);
+    Ok((zones, fans, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A start time on a 10-minute boundary, so every ring's steps line up.
+    const T0: u64 = 1_700_000_400;
+
+    fn sample(unix: u64, temp: f64, failsafe: bool) -> Sample {
+        Sample {
+            unix: unix as f64,
+            mode: if failsafe { "failsafe" } else { "normal" }.to_string(),
+            failsafe,
+            temps: vec![Some(temp), None],
+            duties: vec![Some(30)],
+        }
+    }
+
+    fn names() -> (Vec<String>, Vec<String>) {
+        (vec!["cpu".into(), "mem".into()], vec!["fan1".into()])
+    }
+
+    fn temp_path(name: &str) -> String {
+        let file = format!("fevm-archive-{name}-{}", std::process::id());
+        std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn steps_are_averaged_and_flagged() {
+        let path = temp_path("steps");
+        let (zones, fans) = names();
+        let mut archive = Archive::open(&path, zones.clone(), fans.clone()).unwrap();
+        for i in 0..120 {
+            let temp = if i < 60 { 40.0 } else { 50.0 + (i % 2) as f64 };
+            archive.record(&sample(T0 + i, temp, i == 70)).unwrap();
+        }
+        archive.flush().unwrap();
+
+        let (read_zones, read_fans, minutes) = read(&path, 1).unwrap();
+        assert_eq!((read_zones, read_fans), (zones.clone(), fans.clone()));
+        assert_eq!(minutes.len(), 2);
+        assert_eq!(minutes[0].unix, T0 as f64);
+        assert_eq!(minutes[0].temps, vec![Some(40.0), None]);
+        assert_eq!(minutes[0].duties, vec![Some(30)]);
+        assert!(!minutes[0].failsafe);
+        assert_eq!(minutes[1].temps, vec![Some(50.5), None]);
+        assert!(minutes[1].failsafe);
+        assert_eq!(read(&path, 0).unwrap().2.len(), 120);
+
+        // Reopened with the same layout the data stays; with another it
+        // starts over.
+        drop(Archive::open(&path, zones.clone(), fans).unwrap());
+        assert_eq!(read(&path, 1).unwrap().2.len(), 2);
+        drop(Archive::open(&path, zones, Vec::new()).unwrap());
+        assert!(read(&path, 1).unwrap().2.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_full_ring_keeps_the_latest_lap_in_order() {
+        let path = temp_path("wrap");
+        let (zones, fans) = names();
+        let mut archive = Archive::open(&path, zones, fans).unwrap();
+        let rows = RINGS[0].1;
+        for i in 0..rows + 100 {
+            archive.record(&sample(T0 + i, i as f64, false)).unwrap();
+        }
+        archive.flush().unwrap();
+
+        let (_, _, seconds) = read(&path, 0).unwrap();
+        assert_eq!(seconds.len() as u64, rows);
+        assert_eq!(seconds[0].unix, (T0 + 100) as f64);
+        assert_eq!(seconds[0].temps[0], Some(100.0));
+        let last = seconds.last().unwrap();
+        assert_eq!(last.unix, (T0 + rows + 99) as f64);
+        assert_eq!(last.temps[0], Some((rows + 99) as f64));
+        assert!(seconds.windows(2).all(|w| w[1].unix == w[0].unix + 1.0));
+        fs::remove_file(&path).unwrap();
+    }
+}
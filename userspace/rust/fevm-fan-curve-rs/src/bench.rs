@@ -0,0 +1,229 @@
+use crate::selftest::running_daemon;
+use crate::{lock_instance, open_outputs, resolve_battery, resolve_sensors, Config};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time between rounds. Drivers that cache readings (nct6775 for about a
+/// second, some ECs longer) then show both cached and fresh reads.
+const ROUND_MS: u64 = 200;
+/// Share of `poll_sec` the I/O of one cycle may take before it is flagged.
+const BUSY_SHARE: f64 = 0.2;
+
+/// Latencies of one sensor or fan operation.
+struct Series {
+    name: String,
+    times: Vec<Duration>,
+    errors: usize,
+    first_error: Option<String>,
+}
+
+impl Series {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            times: Vec::new(),
+            errors: 0,
+            first_error: None,
+        }
+    }
+
+    /// Times `op`, returning how long it took.
+    fn time<T, E: std::fmt::Display>(&mut self, op: impl FnOnce() -> Result<T, E>) -> Duration {
+        let start = Instant::now();
+        let result = op();
+        let took = start.elapsed();
+        match result {
+            Ok(_) => self.times.push(took),
+            Err(e) => {
+                self.errors += 1;
+                self.first_error.get_or_insert_with(|| e.to_string());
+            }
+        }
+        took
+    }
+}
+
+fn ms(d: Duration) -> String {
+    format!("{:.2}", d.as_secs_f64() * 1000.0)
+}
+
+/// The `p` quantile of `times`, which must be sorted.
+fn quantile(times: &[Duration], p: f64) -> Duration {
+    times[((times.len() - 1) as f64 * p).round() as usize]
+}
+
+/// Measures the read latency of every sensor and the write (and read-back)
+/// latency of every fan over `samples` rounds, and how much of `poll_sec`
+/// one cycle's I/O takes. Each fan is written the duty it already has, so
+/// the fans stay where they are; fans whose duty cannot be read back (the
+/// write-only FEVM WMI nodes) are not written at all.
+pub fn run(cfg: &Config, samples: usize, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if samples == 0 {
+        return Err("--samples must be at least 1".into());
+    }
+    if let Some(pid) = running_daemon(cfg) {
+        return Err(format!("the daemon is running (pid {pid}); stop it first").into());
+    }
+    let _lock = if Path::new(&cfg.runtime_dir).is_dir() {
+        lock_instance(cfg)?
+    } else {
+        None
+    };
+    crate::conflict::check(force)?;
+    let term = Arc::new(AtomicBool::new(false));
+    for sig in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(sig, Arc::clone(&term))?;
+    }
+
+    let (cpu_sensors, mem_sensors) = resolve_sensors(cfg)?;
+    let mut sensors = Vec::new();
+    // mem falls back to the cpu sensors when it has none of its own.
+    for source in cpu_sensors
+        .into_iter()
+        .chain(mem_sensors)
+        .chain(resolve_battery(cfg))
+    {
+        let name = source.describe();
+        if !sensors.iter().any(|(s, _): &(Series, _)| s.name == name) {
+            sensors.push((Series::new(name), source));
+        }
+    }
+    let mut outputs = open_outputs(cfg)?;
+    let duties: Vec<Option<i32>> = outputs.iter_mut().map(|o| o.read()).collect();
+    let skipped: Vec<&str> = cfg
+        .fans
+        .iter()
+        .zip(&duties)
+        .filter(|(_, d)| d.is_none())
+        .map(|(f, _)| f.name.as_str())
+        .collect();
+    let mut writes: Vec<Series> = cfg
+        .fans
+        .iter()
+        .map(|f| Series::new(format!("{} write", f.name)))
+        .collect();
+    let mut reads: Vec<Series> = cfg
+        .fans
+        .iter()
+        .map(|f| Series::new(format!("{} read-back", f.name)))
+        .collect();
+    let mut cycles = Vec::new();
+
+    println!(
+        "{samples} rounds, {ROUND_MS}ms apart, {} sensor(s), {} fan(s)",
+        sensors.len(),
+        outputs.len() - skipped.len()
+    );
+    if !skipped.is_empty() {
+        println!(
+            "not written, current duty cannot be read: {}",
+            skipped.join(", ")
+        );
+    }
+    for _ in 0..samples {
+        if term.load(Ordering::Relaxed) {
+            break;
+        }
+        let round = Instant::now();
+        let mut cycle = Duration::ZERO;
+        for (series, source) in &mut sensors {
+            cycle += series.time(|| source.read());
+        }
+        for (i, output) in outputs.iter_mut().enumerate() {
+            let Some(duty) = duties[i] else {
+                continue;
+            };
+            cycle += writes[i].time(|| output.write(duty));
+            cycle += reads[i].time(|| output.read().ok_or("no read-back"));
+        }
+        cycles.push(cycle);
+        thread::sleep(Duration::from_millis(ROUND_MS).saturating_sub(round.elapsed()));
+    }
+    for (output, fan) in outputs.iter_mut().zip(&cfg.fans) {
+        if let Err(e) = output.release() {
+            log_at!(Error, "{}: release failed: {e}", fan.name);
+        }
+    }
+
+    // Outputs without read-back fail every read; leave them out, and the
+    // writes of the fans skipped above.
+    reads.retain(|s| !s.times.is_empty());
+    writes.retain(|s| !s.times.is_empty() || s.errors > 0);
+    let header = ["", "n", "err", "min", "p50", "p95", "p99", "max (ms)"];
+    let mut rows = vec![header.map(String::from).to_vec()];
+    let mut errors = Vec::new();
+    for series in sensors
+        .iter_mut()
+        .map(|(s, _)| s)
+        .chain(&mut writes)
+        .chain(&mut reads)
+    {
+        series.times.sort();
+        let mut row = vec![
+            series.name.clone(),
+            series.times.len().to_string(),
+            series.errors.to_string(),
+        ];
+        if series.times.is_empty() {
+            row.extend(["-"; 5].map(String::from));
+        } else {
+            row.extend([0.0, 0.5, 0.95, 0.99, 1.0].map(|p| ms(quantile(&series.times, p))));
+        }
+        rows.push(row);
+        if let Some(e) = &series.first_error {
+            errors.push(format!(
+                "{}: {} error(s), first: {e}",
+                series.name, series.errors
+            ));
+        }
+    }
+    let mut widths = vec![0; header.len()];
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, w))| match i {
+                0 => format!("{cell:<w$}"),
+                _ => format!("{cell:>w$}"),
+            })
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+    for e in &errors {
+        println!("{e}");
+    }
+
+    if cycles.is_empty() {
+        return Err("interrupted".into());
+    }
+    cycles.sort();
+    let p95 = quantile(&cycles, 0.95);
+    let share = p95.as_secs_f64() / cfg.poll_sec;
+    println!(
+        "I/O per cycle: p50 {}ms, p95 {}ms, {:.1}% of poll_sec ({}s)",
+        ms(quantile(&cycles, 0.5)),
+        ms(p95),
+        share * 100.0,
+        cfg.poll_sec
+    );
+    if share > BUSY_SHARE {
+        println!(
+            "the cycle spends more than {:.0}% of poll_sec on I/O; consider poll_sec >= {:.1}",
+            BUSY_SHARE * 100.0,
+            p95.as_secs_f64() / BUSY_SHARE
+        );
+    }
+    if term.load(Ordering::Relaxed) {
+        return Err("interrupted".into());
+    }
+    Ok(())
+}
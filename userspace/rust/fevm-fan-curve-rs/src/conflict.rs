@@ -0,0 +1,215 @@
+use std::fs;
+
+/// Process names of other fan controllers, and what to call them.
+const KNOWN: [(&str, &str); 8] = [
+    ("fancontrol", "lm-sensors fancontrol"),
+    ("nbfc_service", "NBFC"),
+    ("coolercontrold", "CoolerControl"),
+    ("thinkfan", "thinkfan"),
+    ("fan2go", "fan2go"),
+    ("i8kmon", "i8kutils i8kmon"),
+    ("fevm-fan-curve.py", "the Python fevm-fan-curve daemon"),
+    ("thermald", "thermald"),
+];
+
+/// thermald only touches fans when its config lists one as a cooling device.
+const THERMALD_CONF: &str = "/etc/thermald/thermal-conf.xml";
+
+pub struct Conflict {
+    pub pid: u32,
+    pub what: &'static str,
+}
+
+/// Interpreters whose first non-option argument is the program that runs.
+const INTERPRETERS: [&str; 6] = ["sh", "bash", "dash", "python", "python3", "perl"];
+
+fn basename(s: &str) -> &str {
+    s.rsplit('/').next().unwrap_or(s)
+}
+
+/// The contents of each `<tag>...</tag>` in `xml`, ignoring case.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let lower = xml.to_ascii_lowercase();
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find(&open).map(|i| pos + i + open.len()) {
+        let Some(end) = lower[start..].find(&close).map(|i| start + i) else {
+            break;
+        };
+        out.push(&xml[start..end]);
+        pos = end + close.len();
+    }
+    out
+}
+
+/// Whether a cooling device type names a fan: "Fan", "_FAN0", but not
+/// "fanless".
+fn is_fan_type(ty: &str) -> bool {
+    ty.split(|c: char| !c.is_ascii_alphanumeric()).any(|word| {
+        word.to_ascii_lowercase()
+            .strip_prefix("fan")
+            .is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit()))
+    })
+}
+
+/// Whether `xml`, a thermald config, has a `<CoolingDevice>` whose `<Type>`
+/// is a fan. Comments are left out.
+fn cools_with_fans(xml: &str) -> bool {
+    let mut text = String::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<!--") {
+        text.push_str(&rest[..start]);
+        rest = rest[start..]
+            .find("-->")
+            .map_or("", |end| &rest[start + end + 3..]);
+    }
+    text.push_str(rest);
+    elements(&text, "coolingdevice")
+        .into_iter()
+        .flat_map(|device| elements(device, "type"))
+        .any(|ty| is_fan_type(ty.trim()))
+}
+
+fn thermald_cools_with_fans() -> bool {
+    fs::read_to_string(THERMALD_CONF).is_ok_and(|xml| cools_with_fans(&xml))
+}
+
+/// The name a process runs as: `comm`, plus from the command line the
+/// program, or the script when that is an interpreter, since `comm` is the
+/// interpreter when started as `bash fancontrol` or
+/// `python3 fevm-fan-curve.py`. Arguments such as the file in
+/// `vim fancontrol` are not looked at.
+fn process_names(comm: &str, cmdline: &[u8]) -> Vec<String> {
+    let mut names = vec![comm.trim().to_string()];
+    let mut args = cmdline
+        .split(|b| *b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned());
+    if let Some(program) = args.next() {
+        let program = basename(&program).to_string();
+        // python3.12 and the like.
+        let interpreter = program.split('.').next().unwrap_or_default();
+        if INTERPRETERS.contains(&interpreter) {
+            names.extend(
+                args.find(|arg| !arg.starts_with('-'))
+                    .map(|script| basename(&script).to_string()),
+            );
+        }
+        names.push(program);
+    }
+    names
+}
+
+/// Running processes that look like another fan controller.
+pub fn find() -> Vec<Conflict> {
+    let own = std::process::id();
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut out = Vec::new();
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == own {
+            continue;
+        }
+        let dir = entry.path();
+        let comm = fs::read_to_string(dir.join("comm")).unwrap_or_default();
+        let cmdline = fs::read(dir.join("cmdline")).unwrap_or_default();
+        let names = process_names(&comm, &cmdline);
+        let Some((_, what)) = KNOWN
+            .iter()
+            .find(|(name, _)| names.iter().any(|n| n == name))
+        else {
+            continue;
+        };
+        if *what == "thermald" && !thermald_cools_with_fans() {
+            continue;
+        }
+        out.push(Conflict { pid, what });
+    }
+    out
+}
+
+/// Refuses to go on while another controller is running, unless `force`.
+pub fn check(force: bool) -> Result<(), String> {
+    let found = find();
+    for c in &found {
+        log_at!(
+            Warning,
+            "conflicting fan controller: {} (pid {})",
+            c.what,
+            c.pid
+        );
+    }
+    if found.is_empty() {
+        return Ok(());
+    }
+    if force {
+        log_at!(
+            Warning,
+            "--force given, starting anyway; both controllers will fight over the fans"
+        );
+        return Ok(());
+    }
+    if found.iter().any(|c| c.what == "thermald") {
+        log_at!(
+            Warning,
+            "thermald drives fans listed in {THERMALD_CONF}; remove them there"
+        );
+    }
+    Err("another fan controller is running; stop it or pass --force".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmdline(args: &[&str]) -> Vec<u8> {
+        args.iter().flat_map(|a| a.bytes().chain([0])).collect()
+    }
+
+    #[test]
+    fn thermald_fan_cooling_device() {
+        let xml = "<ThermalConfiguration><Platform><CoolingDevices>\
+                   <CoolingDevice><Type>_Fan0</Type><Path>/sys/x</Path></CoolingDevice>\
+                   </CoolingDevices></Platform></ThermalConfiguration>";
+        assert!(cools_with_fans(xml));
+        assert!(cools_with_fans(&xml.replace("_Fan0", "FAN")));
+    }
+
+    #[test]
+    fn thermald_without_fans() {
+        let xml = "<!-- add a <CoolingDevice><Type>fan</Type></CoolingDevice> here -->\
+                   <Name>fanless box</Name>\
+                   <CoolingDevice><Type>rapl_controller</Type></CoolingDevice>\
+                   <CoolingDevice><Type>fanless_passive</Type></CoolingDevice>";
+        assert!(!cools_with_fans(xml));
+        assert!(!is_fan_type("fanless"));
+        assert!(!is_fan_type("Processor"));
+    }
+
+    #[test]
+    fn program_and_interpreted_script() {
+        let names = process_names("fancontrol\n", &cmdline(&["/usr/sbin/fancontrol"]));
+        assert!(names.contains(&"fancontrol".to_string()));
+        let names = process_names(
+            "python3",
+            &cmdline(&["/usr/bin/python3.12", "-u", "/opt/fevm-fan-curve.py"]),
+        );
+        assert!(names.contains(&"fevm-fan-curve.py".to_string()));
+    }
+
+    #[test]
+    fn arguments_are_not_programs() {
+        let names = process_names("vim", &cmdline(&["vim", "fancontrol"]));
+        assert!(!names.contains(&"fancontrol".to_string()));
+        let names = process_names("less", &cmdline(&["less", "/etc/fancontrol.conf"]));
+        assert_eq!(names, ["less", "less"]);
+    }
+}
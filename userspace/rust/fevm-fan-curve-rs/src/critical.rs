@@ -0,0 +1,97 @@
+use crate::Config;
+use std::process::Command;
+use std::thread;
+use std::time::Instant;
+
+/// The action is not repeated sooner than this, so a machine that comes
+/// back from suspend still hot is not put straight back to sleep in a loop.
+const ACTION_RETRY_SEC: f64 = 600.0;
+
+/// Watches for temperatures above `shutdown_c`. While above, the fans are
+/// run at `max_duty`; once that has not brought the temperature down for
+/// `shutdown_sec`, `shutdown_action` is run as a last resort.
+#[derive(Default)]
+pub struct Critical {
+    since: Option<Instant>,
+    last_action: Option<Instant>,
+}
+
+impl Critical {
+    /// Whether the last reading was above `shutdown_c`.
+    pub fn active(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// Feeds this cycle's hottest reading. With `complete` false some
+    /// sensor failed, so a reading below the limit does not clear it.
+    pub fn update(&mut self, cfg: &Config, hottest: f64, complete: bool) {
+        let Some(limit) = cfg.shutdown_c else {
+            return;
+        };
+        if hottest < limit {
+            if !complete {
+                return;
+            }
+            if self.since.take().is_some() {
+                log_at!(Info, "critical temperature cleared ({hottest:.1}C)");
+            }
+            return;
+        }
+        let since = *self.since.get_or_insert_with(|| {
+            log_at!(
+                Error,
+                "critical temperature {hottest:.1}C >= shutdown_c {limit}C; fans at {}%, \
+                 {} in {}s unless it drops",
+                cfg.max_duty,
+                cfg.shutdown_action,
+                cfg.shutdown_sec
+            );
+            Instant::now()
+        });
+        if since.elapsed().as_secs_f64() < cfg.shutdown_sec
+            || self
+                .last_action
+                .is_some_and(|t| t.elapsed().as_secs_f64() < ACTION_RETRY_SEC)
+        {
+            return;
+        }
+        self.last_action = Some(Instant::now());
+        log_at!(
+            Error,
+            "above {limit}C for {:.0}s with fans at {}%; running shutdown action {}",
+            since.elapsed().as_secs_f64(),
+            cfg.max_duty,
+            cfg.shutdown_action
+        );
+        run_action(cfg);
+    }
+}
+
+fn run_action(cfg: &Config) {
+    let mut cmd = match cfg.shutdown_action.as_str() {
+        "poweroff" | "suspend" => {
+            let mut cmd = Command::new("systemctl");
+            cmd.arg(&cfg.shutdown_action);
+            cmd
+        }
+        _ => {
+            let mut cmd = Command::new("/bin/sh");
+            cmd.arg("-c")
+                .arg(cfg.shutdown_command.as_deref().unwrap_or_default());
+            cmd
+        }
+    };
+    match cmd.spawn() {
+        // Reaped on its own thread; the loop keeps the fans at max meanwhile.
+        Ok(mut child) => {
+            thread::spawn(move || {
+                if let Ok(status) = child.wait() {
+                    if !status.success() {
+                        log_at!(Error, "shutdown action exited with {status}");
+                    }
+                }
+            });
+        }
+        Err(e) => log_at!(Error, "cannot run shutdown action: {e}"),
+    }
+}
@@ -0,0 +1,523 @@
+use crate::nvml_sensor::NvmlSensor;
+use crate::power_supply::PowerSupplySource;
+use crate::sensor::SensorSource;
+use crate::wasm_sensor::WasmSensor;
+use crate::Config;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Chips whose temperature is the CPU package, best first.
+const CPU_CHIPS: [&str; 5] = [
+    "k10temp",
+    "zenpower",
+    "coretemp",
+    "cpu_thermal",
+    "cpu0_thermal",
+];
+/// DIMM temperature sensors.
+const MEM_CHIPS: [&str; 2] = ["spd5118", "jc42"];
+/// Super I/O chips, which expose many channels of which only some are wired.
+const SUPERIO_PREFIXES: [&str; 3] = ["nct", "it8", "f718"];
+
+const FEVM_DIR: &str = "/sys/devices/platform/fevm-ip3-wmi";
+const THINKPAD_FAN: &str = "/proc/acpi/ibm/fan";
+
+/// One `tempN_input` of a chip.
+pub struct Temp {
+    pub channel: String,
+    pub label: Option<String>,
+    pub celsius: Option<f64>,
+}
+
+/// What a hwmon chip exposes.
+pub struct Chip {
+    pub name: String,
+    pub dir: String,
+    pub temps: Vec<Temp>,
+    pub pwms: Vec<u32>,
+    pub tachs: Vec<(u32, Option<u32>)>,
+}
+
+impl Chip {
+    fn is_superio(&self) -> bool {
+        SUPERIO_PREFIXES.iter().any(|p| self.name.starts_with(p))
+    }
+
+    /// The hwmon node of fevm-ip3-wmi, whose fans are the built-in fan1
+    /// and fan2 already.
+    fn is_fevm(&self) -> bool {
+        self.name.starts_with("fevm")
+            || fs::canonicalize(format!("{}/device", self.dir))
+                .is_ok_and(|d| d.starts_with(FEVM_DIR))
+    }
+}
+
+fn read_trim(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Numbers N of the `<prefix>N<suffix>` files in `dir`, sorted.
+fn channels(dir: &Path, prefix: &str, suffix: &str) -> Vec<u32> {
+    let mut out: Vec<u32> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.strip_prefix(prefix)?
+                .strip_suffix(suffix)?
+                .parse()
+                .ok()
+        })
+        .collect();
+    out.sort_unstable();
+    out
+}
+
+/// Every hwmon chip with its temperatures, pwm outputs and tach inputs.
+pub fn chips() -> Vec<Chip> {
+    let mut dirs: Vec<_> = fs::read_dir("/sys/class/hwmon")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .collect();
+    // hwmon10 after hwmon9.
+    dirs.sort_by_key(|d| {
+        let name = d
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        (name.len(), name)
+    });
+    dirs.into_iter()
+        .filter_map(|dir| {
+            let name = read_trim(&dir.join("name"))?;
+            let temps = channels(&dir, "temp", "_input")
+                .into_iter()
+                .map(|n| {
+                    let raw = read_trim(&dir.join(format!("temp{n}_input")));
+                    Temp {
+                        channel: format!("temp{n}"),
+                        label: read_trim(&dir.join(format!("temp{n}_label"))),
+                        celsius: raw.and_then(|v| v.parse::<f64>().ok()).map(|v| v / 1000.0),
+                    }
+                })
+                .collect();
+            let tachs = channels(&dir, "fan", "_input")
+                .into_iter()
+                .map(|n| {
+                    let rpm = read_trim(&dir.join(format!("fan{n}_input")));
+                    (n, rpm.and_then(|v| v.parse().ok()))
+                })
+                .collect();
+            Some(Chip {
+                name,
+                dir: dir.to_string_lossy().to_string(),
+                temps,
+                pwms: channels(&dir, "pwm", ""),
+                tachs,
+            })
+        })
+        .collect()
+}
+
+/// A plausible reading: unconnected Super I/O inputs read -128, 0 or 127.
+pub fn plausible(celsius: Option<f64>) -> bool {
+    celsius.is_some_and(|t| t > 5.0 && t < 115.0)
+}
+
+/// Sensor names for a zone: the chip name, or `chip/label` for the wired
+/// channels of a Super I/O chip matching `label_hint`.
+fn superio_names(chips: &[Chip], label_hint: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for chip in chips.iter().filter(|c| c.is_superio()) {
+        for t in &chip.temps {
+            let Some(label) = &t.label else { continue };
+            if label.to_ascii_uppercase().contains(label_hint) && plausible(t.celsius) {
+                out.push(format!("{}/{label}", chip.name));
+            }
+        }
+    }
+    out
+}
+
+fn zone_names(chips: &[Chip], known: &[&str]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for name in known {
+        if chips
+            .iter()
+            .any(|c| c.name == *name && c.temps.iter().any(|t| plausible(t.celsius)))
+        {
+            out.push(name.to_string());
+            // One CPU chip is enough; every DIMM sensor shares a name anyway.
+            break;
+        }
+    }
+    out
+}
+
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+fn list(names: &[String]) -> String {
+    let items: Vec<String> = names.iter().map(|n| quote(n)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// A `[[fans]]` entry for one detected output.
+struct Fan {
+    name: String,
+    zone: &'static str,
+    enabled: bool,
+    lines: Vec<String>,
+    note: String,
+}
+
+/// An entry for every pwm output. Super I/O channels whose fan is not
+/// spinning are most likely not wired and come out disabled.
+fn hwmon_fans(chips: &[Chip]) -> Vec<Fan> {
+    let mut out = Vec::new();
+    for chip in chips.iter().filter(|c| !c.is_fevm()) {
+        for &n in &chip.pwms {
+            let backend = match chip.name.as_str() {
+                "cros_ec" => vec![
+                    "backend = \"cros_ec\"".to_string(),
+                    format!("channel = {n}"),
+                ],
+                "dell_smm" => vec![
+                    "backend = \"dell_smm\"".to_string(),
+                    format!("channel = {n}"),
+                ],
+                "pwmfan" if n == 1 => vec!["backend = \"pwm_fan\"".to_string()],
+                "gpio_fan" if n == 1 => vec!["backend = \"gpio_fan\"".to_string()],
+                _ => vec![
+                    "backend = \"hwmon\"".to_string(),
+                    format!("chip = {}", quote(&chip.name)),
+                    format!("channel = {n}"),
+                ],
+            };
+            let mut lines = backend;
+            let mut note = format!("{} pwm{n}", chip.name);
+            let tach = chip.tachs.iter().find(|(t, _)| *t == n);
+            match tach {
+                Some((_, rpm)) => {
+                    // hwmonN numbering can change across boots; only selftest uses it.
+                    let path = format!("{}/fan{n}_input", chip.dir);
+                    lines.push(format!("rpm_path = {}", quote(&path)));
+                    if let Some(rpm) = rpm {
+                        let _ = write!(note, ", now {rpm} rpm");
+                    }
+                }
+                None => note.push_str(", no tach"),
+            }
+            let spinning = tach.is_some_and(|(_, rpm)| rpm.is_some_and(|r| r > 0));
+            let enabled = !chip.is_superio() || spinning;
+            if !enabled {
+                note.push_str("; not spinning, probably not wired");
+            }
+            out.push(Fan {
+                name: format!("{}_{n}", chip.name),
+                zone: "cpu",
+                enabled,
+                lines,
+                note,
+            });
+        }
+    }
+    out
+}
+
+/// Probes the hardware and returns a config with the sensors, zones and
+/// outputs found, and curves that err on the side of cooling.
+pub fn propose() -> String {
+    let chips = chips();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# Generated by `fevm-fan-curve-rs detect`. Review before use, then"
+    );
+    let _ = writeln!(out, "# check it with `validate` and `selftest`.");
+    let _ = writeln!(out, "#");
+    let _ = writeln!(out, "# hwmon chips found:");
+    for chip in &chips {
+        let temps: Vec<String> = chip
+            .temps
+            .iter()
+            .map(|t| {
+                let name = t.label.as_deref().unwrap_or(&t.channel);
+                match t.celsius {
+                    Some(c) if plausible(Some(c)) => format!("{name}={c:.1}"),
+                    _ => format!("{name}=?"),
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "#   {:<14} {} [{}]",
+            chip.name,
+            chip.dir,
+            if temps.is_empty() {
+                "no temperatures".to_string()
+            } else {
+                temps.join(" ")
+            }
+        );
+    }
+
+    let mut cpu = zone_names(&chips, &CPU_CHIPS);
+    if cpu.is_empty() {
+        cpu = superio_names(&chips, "CPU");
+    }
+    let mut mem = zone_names(&chips, &MEM_CHIPS);
+    if mem.is_empty() {
+        mem = superio_names(&chips, "DIMM");
+    }
+
+    let _ = writeln!(out, "\n[general]");
+    let _ = writeln!(out, "poll_sec = 1");
+    let _ = writeln!(out, "min_duty = 25");
+    let _ = writeln!(out, "failsafe_duty = 80");
+
+    let _ = writeln!(out, "\n[sensors]");
+    if cpu.is_empty() {
+        let _ = writeln!(
+            out,
+            "# No CPU sensor recognised; pick one from the list above."
+        );
+        let _ = writeln!(out, "cpu_names = []");
+    } else {
+        let _ = writeln!(out, "cpu_names = {}", list(&cpu));
+    }
+    if mem.is_empty() {
+        let _ = writeln!(
+            out,
+            "# No memory sensor found; the mem zone follows the CPU."
+        );
+        let _ = writeln!(out, "mem_names = []");
+    } else {
+        let _ = writeln!(out, "mem_names = {}", list(&mem));
+    }
+    let _ = writeln!(out, "mem_fallback_to_cpu = true");
+
+    let _ = writeln!(out, "\n[curves]");
+    let _ = writeln!(
+        out,
+        "cpu = [[40, 30], [55, 40], [65, 60], [75, 80], [85, 100]]"
+    );
+    let _ = writeln!(
+        out,
+        "mem = [[35, 30], [50, 45], [60, 65], [70, 85], [80, 100]]"
+    );
+
+    let fevm = [1, 2].map(|n| Path::new(&format!("{FEVM_DIR}/fan{n}_duty")).exists());
+    for (n, found) in fevm.iter().enumerate() {
+        if !found {
+            let _ = writeln!(out, "\n# {FEVM_DIR}/fan{}_duty not present", n + 1);
+            let _ = writeln!(out, "[[fans]]\nname = \"fan{}\"\nenabled = false", n + 1);
+        }
+    }
+    if fevm.iter().any(|f| *f) {
+        let _ = writeln!(
+            out,
+            "\n# fan1/fan2: FEVM WMI outputs (built in; fan2 follows mem)."
+        );
+    }
+
+    let mut fans = hwmon_fans(&chips);
+    if Path::new(THINKPAD_FAN).exists() {
+        fans.push(Fan {
+            name: "thinkpad".to_string(),
+            zone: "cpu",
+            enabled: true,
+            lines: vec!["backend = \"thinkpad\"".to_string()],
+            note: format!("{THINKPAD_FAN} (needs thinkpad_acpi fan_control=1)"),
+        });
+    }
+    for fan in &fans {
+        let _ = writeln!(out, "\n# {}", fan.note);
+        let _ = writeln!(out, "[[fans]]");
+        let _ = writeln!(out, "name = {}", quote(&fan.name));
+        let _ = writeln!(out, "zone = \"{}\"", fan.zone);
+        if !fan.enabled {
+            let _ = writeln!(out, "enabled = false");
+        }
+        for line in &fan.lines {
+            let _ = writeln!(out, "{line}");
+        }
+    }
+
+    let pwmchips: Vec<String> = fs::read_dir("/sys/class/pwm")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path().to_string_lossy().to_string())
+        .collect();
+    if !pwmchips.is_empty() {
+        let _ = writeln!(
+            out,
+            "\n# Generic PWM controllers, not added since the wiring is unknown \
+             (backend = \"pwmchip\"):"
+        );
+        for chip in pwmchips {
+            let _ = writeln!(out, "#   {chip}");
+        }
+    }
+    if fevm.iter().all(|f| !f) && fans.is_empty() {
+        let _ = writeln!(out, "\n# No fan outputs found; is the fan driver loaded?");
+    }
+    out
+}
+
+/// Whether the configured sensor name `name` (`chip` or `chip/channel`)
+/// selects `temp` of `chip`, the same way the daemon matches it.
+fn selects(name: &str, chip: &Chip, temp: &Temp) -> bool {
+    let (chip_name, channel) = match name.split_once('/') {
+        Some((c, ch)) => (c, Some(ch)),
+        None => (name, None),
+    };
+    chip_name == chip.name
+        && channel.is_none_or(|ch| ch == temp.channel || temp.label.as_deref() == Some(ch))
+}
+
+/// Prints every temperature of the chips the config names (or of all chips
+/// with `all`), with the zones each one feeds, plus any sensor plugins,
+/// NVIDIA GPUs and battery sensors.
+pub fn print_readout(cfg: &Config, all: bool) {
+    let chips = chips();
+    let feeds =
+        |names: &[String], chip: &Chip, temp: &Temp| names.iter().any(|n| selects(n, chip, temp));
+    let mem_falls_back = cfg.mem_fallback_to_cpu
+        && cfg.sensor_plugins.iter().all(|p| p.zone != "mem")
+        && cfg.nvml_sensors.iter().all(|g| g.zone != "mem")
+        && !chips
+            .iter()
+            .any(|c| c.temps.iter().any(|t| feeds(&cfg.mem_sensor_names, c, t)));
+
+    let mut rows: Vec<[String; 5]> = Vec::new();
+    for chip in &chips {
+        for temp in &chip.temps {
+            let mut zones = Vec::new();
+            if feeds(&cfg.cpu_sensor_names, chip, temp) {
+                zones.push("cpu");
+                if mem_falls_back {
+                    zones.push("mem");
+                }
+            }
+            if feeds(&cfg.mem_sensor_names, chip, temp) {
+                zones.push("mem");
+            }
+            let named = |names: &[String]| {
+                names
+                    .iter()
+                    .any(|n| n.split('/').next() == Some(&chip.name))
+            };
+            if !all
+                && zones.is_empty()
+                && !named(&cfg.cpu_sensor_names)
+                && !named(&cfg.mem_sensor_names)
+            {
+                continue;
+            }
+            rows.push([
+                chip.name.clone(),
+                temp.channel.clone(),
+                temp.label.clone().unwrap_or_else(|| "-".to_string()),
+                match temp.celsius {
+                    Some(c) if plausible(Some(c)) => format!("{c:.1}"),
+                    Some(c) => format!("{c:.1}?"),
+                    None => "error".to_string(),
+                },
+                if zones.is_empty() {
+                    "-".to_string()
+                } else {
+                    zones.join(",")
+                },
+            ]);
+        }
+    }
+    for p in &cfg.sensor_plugins {
+        let reading = match WasmSensor::load(&p.name, &p.path, &p.config, p.allow_paths.clone()) {
+            Ok(mut sensor) => match sensor.read() {
+                Ok(c) => format!("{c:.1}"),
+                Err(e) => format!("error: {e}"),
+            },
+            Err(e) => format!("error: {e}"),
+        };
+        rows.push([
+            "wasm".to_string(),
+            p.name.clone(),
+            "-".to_string(),
+            reading,
+            p.zone.clone(),
+        ]);
+    }
+    for g in &cfg.nvml_sensors {
+        let reading = match NvmlSensor::open(&g.name, &g.gpu) {
+            Ok(mut sensor) => match sensor.read() {
+                Ok(c) => format!("{c:.1}"),
+                Err(e) => format!("error: {e}"),
+            },
+            Err(e) => format!("error: {e}"),
+        };
+        rows.push([
+            "nvml".to_string(),
+            g.name.clone(),
+            g.gpu.to_string(),
+            reading,
+            g.zone.clone(),
+        ]);
+    }
+    for name in &cfg.battery_sensor_names {
+        let reading = match PowerSupplySource::open(name) {
+            Ok(mut sensor) => match sensor.read() {
+                Ok(c) => format!("{c:.1}"),
+                Err(e) => format!("error: {e}"),
+            },
+            Err(e) => format!("error: {e}"),
+        };
+        rows.push([
+            "power_supply".to_string(),
+            name.clone(),
+            "-".to_string(),
+            reading,
+            "battery".to_string(),
+        ]);
+    }
+
+    let header = ["CHIP", "CHANNEL", "LABEL", "TEMP_C", "ZONE"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| format!("{cell:<w$}"))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+
+    for (zone, names) in [
+        ("cpu", &cfg.cpu_sensor_names),
+        ("mem", &cfg.mem_sensor_names),
+    ] {
+        for name in names {
+            if !chips
+                .iter()
+                .any(|c| c.temps.iter().any(|t| selects(name, c, t)))
+            {
+                println!("warning: {zone}_names entry \"{name}\" matches no temperature");
+            }
+        }
+    }
+    if mem_falls_back {
+        println!("note: no memory sensor matched; the mem zone follows the CPU");
+    }
+}
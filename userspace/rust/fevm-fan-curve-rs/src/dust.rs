@@ -0,0 +1,88 @@
+use crate::{unix_now, Config};
+use std::fs;
+use std::time::{Duration, Instant};
+
+pub enum Transition {
+    Started,
+    Finished,
+}
+
+/// Schedules the dust-clearing spin-up: every `dust_clean_interval_h`, the
+/// first time the machine is idle and cool, all fans run at `max_duty` for
+/// `dust_clean_sec`. The time of the last run is kept in the state
+/// directory so the schedule survives restarts.
+pub struct DustClean {
+    stamp: String,
+    last_unix: u64,
+    until: Option<Instant>,
+}
+
+/// 1-minute load average.
+fn load_avg() -> Option<f64> {
+    fs::read_to_string("/proc/loadavg")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+impl DustClean {
+    /// Reads the last run time. Without one the clock starts now, so a new
+    /// install does not spin up on its first start.
+    pub fn load(cfg: &Config) -> Self {
+        let stamp = format!("{}/dust-clean", cfg.state_dir);
+        let last_unix = match fs::read_to_string(&stamp).map(|s| s.trim().parse::<u64>()) {
+            Ok(Ok(t)) => t,
+            _ => {
+                let now = unix_now();
+                if cfg.dust_clean_interval_h > 0.0 {
+                    if let Err(e) = fs::write(&stamp, format!("{now}\n")) {
+                        log_at!(Warning, "cannot write {stamp}: {e}");
+                    }
+                }
+                now
+            }
+        };
+        Self {
+            stamp,
+            last_unix,
+            until: None,
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.until.is_some()
+    }
+
+    /// Called once per good cycle with the zone temperatures. Starts a run
+    /// when one is due and the machine is idle and cool, and ends it after
+    /// `dust_clean_sec`.
+    pub fn update(&mut self, cfg: &Config, temps: &[f64]) -> Option<Transition> {
+        if let Some(until) = self.until {
+            if Instant::now() < until {
+                return None;
+            }
+            self.until = None;
+            return Some(Transition::Finished);
+        }
+        if cfg.dust_clean_interval_h <= 0.0 {
+            return None;
+        }
+        let now = unix_now();
+        let due = self.last_unix + (cfg.dust_clean_interval_h * 3600.0) as u64;
+        let cool = temps.iter().all(|&t| t <= cfg.dust_clean_max_temp);
+        let idle = load_avg().is_some_and(|l| l <= cfg.dust_clean_max_load);
+        if now < due || !cool || !idle {
+            return None;
+        }
+        // Stamped before the run, so a crash during it cannot repeat it on
+        // every restart.
+        self.last_unix = now;
+        if let Err(e) = fs::write(&self.stamp, format!("{now}\n")) {
+            log_at!(Warning, "cannot write {}: {e}", self.stamp);
+        }
+        self.until = Some(Instant::now() + Duration::from_secs_f64(cfg.dust_clean_sec));
+        Some(Transition::Started)
+    }
+}
@@ -0,0 +1,99 @@
+use crate::Config;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Instant;
+
+/// Levels it takes to go from `failsafe_duty` to `max_duty`.
+const STEPS: u32 = 3;
+
+/// Tracks how long the loop has been failing without a break, raising the
+/// failsafe response the longer it stays blind.
+#[derive(Default)]
+pub struct Escalation {
+    since: Option<Instant>,
+    level: u32,
+}
+
+impl Escalation {
+    /// Records a failed cycle. Returns the new level when this cycle
+    /// reached the next one.
+    pub fn on_error(&mut self, cfg: &Config) -> Option<u32> {
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if cfg.failsafe_escalate_sec <= 0.0 {
+            return None;
+        }
+        let level = (since.elapsed().as_secs_f64() / cfg.failsafe_escalate_sec) as u32;
+        (level > self.level).then(|| {
+            self.level = level;
+            level
+        })
+    }
+
+    /// Records a good cycle; returns the level that was reached, if any.
+    pub fn on_success(&mut self) -> Option<u32> {
+        self.since = None;
+        let level = std::mem::take(&mut self.level);
+        (level > 0).then_some(level)
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn failing_sec(&self) -> f64 {
+        self.since.map_or(0.0, |t| t.elapsed().as_secs_f64())
+    }
+
+    /// `failsafe_duty`, raised a third of the way to `max_duty` per level.
+    pub fn duty(&self, cfg: &Config) -> i32 {
+        let level = self.level.min(STEPS) as i32;
+        cfg.failsafe_duty + (cfg.max_duty - cfg.failsafe_duty).max(0) * level / STEPS as i32
+    }
+}
+
+/// Runs `alert_command` through the shell without waiting for it. The
+/// event, level, seconds in failsafe and last error are passed as
+/// FEVM_EVENT, FEVM_LEVEL, FEVM_FAILSAFE_SEC and FEVM_ERROR.
+pub fn alert(cfg: &Config, event: &str, level: u32, failing_sec: f64, error: &str) {
+    let Some(cmd) = &cfg.alert_command else {
+        return;
+    };
+    let spawned = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("FEVM_EVENT", event)
+        .env("FEVM_LEVEL", level.to_string())
+        .env("FEVM_FAILSAFE_SEC", format!("{failing_sec:.0}"))
+        .env("FEVM_ERROR", error)
+        .spawn();
+    match spawned {
+        // Reaped on its own thread so a slow hook cannot hold up the loop.
+        Ok(mut child) => {
+            thread::spawn(move || {
+                if let Ok(status) = child.wait() {
+                    if !status.success() {
+                        log_at!(Warning, "alert_command exited with {status}");
+                    }
+                }
+            });
+        }
+        Err(e) => log_at!(Warning, "cannot run alert_command: {e}"),
+    }
+}
+
+/// Unbinds and rebinds a device, given as its path under a driver, e.g.
+/// `/sys/bus/platform/drivers/nct6775/nct6775.656`.
+pub fn rebind(path: &str) -> Result<(), String> {
+    let path = Path::new(path);
+    let (Some(driver), Some(device)) = (path.parent(), path.file_name()) else {
+        return Err(format!("{}: not a driver/device path", path.display()));
+    };
+    let device = device.to_string_lossy();
+    if path.exists() {
+        fs::write(driver.join("unbind"), device.as_bytes())
+            .map_err(|e| format!("unbind {device}: {e}"))?;
+    }
+    fs::write(driver.join("bind"), device.as_bytes()).map_err(|e| format!("bind {device}: {e}"))
+}
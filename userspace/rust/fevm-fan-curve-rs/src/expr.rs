@@ -0,0 +1,318 @@
+/// Arithmetic expression of one variable, `t`, for curves that are not
+/// naturally piecewise-linear:
+///
+/// - numbers, `t`, parentheses
+/// - `+ - * /`, `^` (power, right-associative), unary `-`
+/// - `min(a, b, ...)`, `max(a, b, ...)`, `clamp(x, lo, hi)`, `abs`, `sqrt`,
+///   `exp`, `ln`, `pow(a, b)`, `floor`, `ceil`, `round`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    Var,
+    Neg(Box<Expr>),
+    Bin(char, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Func {
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Pow,
+    Floor,
+    Ceil,
+    Round,
+}
+
+impl Func {
+    fn lookup(name: &str) -> Option<(Self, usize, Option<usize>)> {
+        // (function, min args, max args; None = any number)
+        Some(match name {
+            "min" => (Self::Min, 1, None),
+            "max" => (Self::Max, 1, None),
+            "clamp" => (Self::Clamp, 3, Some(3)),
+            "abs" => (Self::Abs, 1, Some(1)),
+            "sqrt" => (Self::Sqrt, 1, Some(1)),
+            "exp" => (Self::Exp, 1, Some(1)),
+            "ln" => (Self::Ln, 1, Some(1)),
+            "pow" => (Self::Pow, 2, Some(2)),
+            "floor" => (Self::Floor, 1, Some(1)),
+            "ceil" => (Self::Ceil, 1, Some(1)),
+            "round" => (Self::Round, 1, Some(1)),
+            _ => return None,
+        })
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut p = Parser {
+            src: src.as_bytes(),
+            pos: 0,
+        };
+        let expr = p.sum()?;
+        p.skip_ws();
+        if p.pos < p.src.len() {
+            return Err(p.error("unexpected input"));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, t: f64) -> f64 {
+        match self {
+            Self::Num(v) => *v,
+            Self::Var => t,
+            Self::Neg(e) => -e.eval(t),
+            Self::Bin(op, a, b) => {
+                let (a, b) = (a.eval(t), b.eval(t));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            }
+            Self::Call(f, args) => {
+                let v: Vec<f64> = args.iter().map(|a| a.eval(t)).collect();
+                match f {
+                    Func::Min => v.iter().copied().fold(f64::INFINITY, f64::min),
+                    Func::Max => v.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                    Func::Clamp => v[0].max(v[1]).min(v[2]),
+                    Func::Abs => v[0].abs(),
+                    Func::Sqrt => v[0].sqrt(),
+                    Func::Exp => v[0].exp(),
+                    Func::Ln => v[0].ln(),
+                    Func::Pow => v[0].powf(v[1]),
+                    Func::Floor => v[0].floor(),
+                    Func::Ceil => v[0].ceil(),
+                    Func::Round => v[0].round(),
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> String {
+        format!("{msg} at column {}", self.pos + 1)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.src.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.product()?;
+        while let Some(op @ (b'+' | b'-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.product()?;
+            lhs = Expr::Bin(op as char, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    // product := unary (('*' | '/') unary)*
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op @ (b'*' | b'/')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            lhs = Expr::Bin(op as char, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    // unary := '-' unary | power
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat(b'-') {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    // power := atom ('^' unary)?
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.atom()?;
+        if self.eat(b'^') {
+            let exp = self.unary()?;
+            return Ok(Expr::Bin('^', Box::new(base), Box::new(exp)));
+        }
+        Ok(base)
+    }
+
+    // atom := number | 't' | name '(' args ')' | '(' sum ')'
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let inner = self.sum()?;
+                if !self.eat(b')') {
+                    return Err(self.error("expected ')'"));
+                }
+                Ok(inner)
+            }
+            Some(c) if c.is_ascii_digit() || c == b'.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => self.name(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Expr, String> {
+        let start = self.pos;
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_digit() || *c == b'.')
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        text.parse().map(Expr::Num).map_err(|_| {
+            self.pos = start;
+            self.error(&format!("bad number '{text}'"))
+        })
+    }
+
+    fn name(&mut self) -> Result<Expr, String> {
+        let start = self.pos;
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'_')
+        {
+            self.pos += 1;
+        }
+        let name = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        if name == "t" {
+            return Ok(Expr::Var);
+        }
+        let Some((func, min, max)) = Func::lookup(name) else {
+            self.pos = start;
+            return Err(self.error(&format!("unknown name '{name}'")));
+        };
+        if !self.eat(b'(') {
+            return Err(self.error(&format!("expected '(' after {name}")));
+        }
+        let mut args = vec![self.sum()?];
+        while self.eat(b',') {
+            args.push(self.sum()?);
+        }
+        if !self.eat(b')') {
+            return Err(self.error("expected ')'"));
+        }
+        if args.len() < min || max.is_some_and(|max| args.len() > max) {
+            self.pos = start;
+            return Err(self.error(&format!(
+                "{name} takes {} argument(s), got {}",
+                match max {
+                    Some(max) if max == min => min.to_string(),
+                    Some(max) => format!("{min}..{max}"),
+                    None => format!("{min} or more"),
+                },
+                args.len()
+            )));
+        }
+        Ok(Expr::Call(func, args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(src: &str, t: f64) -> f64 {
+        Expr::parse(src).unwrap().eval(t)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3", 0.0), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", 0.0), 9.0);
+        assert_eq!(eval("10 - 4 - 3", 0.0), 3.0);
+        assert_eq!(eval("12 / 3 / 2", 0.0), 2.0);
+    }
+
+    #[test]
+    fn power_is_right_associative_and_binds_tighter_than_minus() {
+        assert_eq!(eval("2 ^ 3 ^ 2", 0.0), 512.0);
+        assert_eq!(eval("-t ^ 2", 3.0), -9.0);
+        assert_eq!(eval("2 ^ -1", 0.0), 0.5);
+    }
+
+    #[test]
+    fn variable_and_functions() {
+        assert_eq!(eval("clamp((t - 40) * 2, 20, 100)", 30.0), 20.0);
+        assert_eq!(eval("clamp((t - 40) * 2, 20, 100)", 60.0), 40.0);
+        assert_eq!(eval("clamp((t - 40) * 2, 20, 100)", 95.0), 100.0);
+        assert_eq!(eval("max(t, 1, 50)", 42.0), 50.0);
+        assert_eq!(eval("min(t)", 42.0), 42.0);
+        assert_eq!(eval("pow(2, 10) + sqrt(16) + abs(-1)", 0.0), 1029.0);
+        assert_eq!(eval("floor(2.7) + ceil(2.2) + round(2.5)", 0.0), 8.0);
+        assert!((eval("ln(exp(t))", 3.0) - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn errors_name_the_column() {
+        assert_eq!(
+            Expr::parse("t + x").unwrap_err(),
+            "unknown name 'x' at column 5"
+        );
+        assert_eq!(
+            Expr::parse("(t + 1").unwrap_err(),
+            "expected ')' at column 7"
+        );
+        assert_eq!(
+            Expr::parse("t 1").unwrap_err(),
+            "unexpected input at column 3"
+        );
+        assert_eq!(
+            Expr::parse("1.2.3").unwrap_err(),
+            "bad number '1.2.3' at column 1"
+        );
+        assert_eq!(
+            Expr::parse("t *").unwrap_err(),
+            "unexpected end of expression at column 4"
+        );
+        assert_eq!(
+            Expr::parse("clamp(t, 1)").unwrap_err(),
+            "clamp takes 3 argument(s), got 2 at column 1"
+        );
+        assert_eq!(
+            Expr::parse("min()").unwrap_err(),
+            "unexpected character at column 5"
+        );
+        assert_eq!(
+            Expr::parse("sqrt t").unwrap_err(),
+            "expected '(' after sqrt at column 6"
+        );
+    }
+}
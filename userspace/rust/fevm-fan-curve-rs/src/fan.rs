@@ -0,0 +1,852 @@
+use crate::usb_hid::{self, Model, UsbHidFan};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// Where a fan's duty ends up. Duties are always percent (0..=100); each
+/// output converts to whatever scale its hardware expects.
+pub trait FanOutput {
+    /// Prepares the output for manual control. Called once before the loop.
+    fn init(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()>;
+
+    /// Current duty as reported by the hardware, when it can be read back.
+    fn read(&mut self) -> Option<i32> {
+        None
+    }
+
+    /// Hands the fan back to whoever controlled it before we started.
+    /// Called once on shutdown.
+    fn release(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Takes the fan back from firmware that switched itself to automatic
+    /// control. Returns `false` when the output has no mode to switch.
+    fn force_manual(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Hands the fan to the firmware's automatic control until the next
+    /// `force_manual`. Returns `false` when the output has no such mode.
+    fn set_auto(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn describe(&self) -> String;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FanBackend {
+    /// A node that takes the duty percent as plain text, like `fan1_duty`
+    /// of the FEVM WMI driver.
+    Sysfs { path: String },
+    /// A channel of a generic PWM controller under /sys/class/pwm.
+    PwmChip {
+        chip: String,
+        channel: u32,
+        period_ns: u64,
+        invert: bool,
+    },
+    /// `pwmN` of the ChromeOS EC hwmon device (0..=255, `pwmN_enable` 1 =
+    /// manual, 2 = EC automatic).
+    CrosEc { channel: u32 },
+    /// `pwmN` of any hwmon chip with the standard pwm interface, such as the
+    /// nct6775 and it87 Super I/O drivers.
+    Hwmon { chip: String, channel: u32 },
+    /// `pwmN` of dell_smm_hwmon. The SMM interface only knows a few fan
+    /// states (`states`, usually off/low/high); `pwm1_enable` switches BIOS
+    /// automatic control off for all fans where the machine allows it.
+    DellSmm { channel: u32, states: u32 },
+    /// thinkpad_acpi's /proc/acpi/ibm/fan: discrete levels 0..=7 plus
+    /// "full-speed", guarded by the firmware watchdog.
+    ThinkPad {
+        path: String,
+        watchdog_sec: u32,
+        full_speed: bool,
+    },
+    /// The pwm-fan hwmon driver common on SBCs (`pwm1`, 0..=255).
+    PwmFan,
+    /// The gpio-fan hwmon driver: `pwm1` is mapped onto a few GPIO speeds.
+    GpioFan,
+    /// A thermal cooling device (`cur_state` 0..=`max_state`), matched by
+    /// its `type` (e.g. "pwm-fan") or given as a path.
+    CoolingDevice { device: String },
+    /// A fan channel (1-based) of a USB controller liquidctl also drives,
+    /// optionally picked by serial number. Needs the hidapi feature.
+    UsbHid {
+        model: Model,
+        channel: u32,
+        serial: Option<String>,
+    },
+}
+
+impl FanBackend {
+    /// Whether the output can hand the fan to firmware automatic control,
+    /// as `failsafe = "auto"` needs.
+    pub fn has_auto(&self) -> bool {
+        matches!(
+            self,
+            Self::CrosEc { .. } | Self::Hwmon { .. } | Self::DellSmm { .. } | Self::ThinkPad { .. }
+        )
+    }
+}
+
+pub fn open_output(backend: &FanBackend) -> io::Result<Box<dyn FanOutput>> {
+    Ok(match backend {
+        FanBackend::Sysfs { path } => Box::new(SysfsDuty { path: path.clone() }),
+        FanBackend::PwmChip {
+            chip,
+            channel,
+            period_ns,
+            invert,
+        } => Box::new(PwmChip {
+            chip: PathBuf::from(chip),
+            channel: *channel,
+            period_ns: *period_ns,
+            invert: *invert,
+        }),
+        FanBackend::CrosEc { channel } => Box::new(HwmonPwm::new(find_hwmon("cros_ec")?, *channel)),
+        FanBackend::Hwmon { chip, channel } => Box::new(HwmonPwm::new(find_hwmon(chip)?, *channel)),
+        FanBackend::DellSmm { channel, states } => Box::new(DellSmm {
+            dir: find_hwmon("dell_smm")?,
+            channel: *channel,
+            states: *states,
+            last: None,
+            saved_enable: None,
+        }),
+        FanBackend::ThinkPad {
+            path,
+            watchdog_sec,
+            full_speed,
+        } => Box::new(ThinkPad {
+            path: path.clone(),
+            watchdog_sec: *watchdog_sec,
+            full_speed: *full_speed,
+            last: None,
+        }),
+        FanBackend::PwmFan => Box::new(HwmonPwm {
+            set_manual: false,
+            ..HwmonPwm::new(find_hwmon("pwmfan")?, 1)
+        }),
+        FanBackend::GpioFan => Box::new(HwmonPwm {
+            verify: false,
+            has_auto: false,
+            ..HwmonPwm::new(find_hwmon("gpio_fan")?, 1)
+        }),
+        FanBackend::CoolingDevice { device } => Box::new(CoolingDevice {
+            dir: find_cooling_device(device)?,
+            max_state: 0,
+            last: None,
+        }),
+        FanBackend::UsbHid {
+            model,
+            channel,
+            serial,
+        } => Box::new(UsbHidFan::open(*model, *channel, serial.as_deref())?),
+    })
+}
+
+/// The files an output for `backend` writes, for checking permissions up
+/// front. Fails the same way `open_output` does when the device is missing.
+pub fn backend_nodes(backend: &FanBackend) -> io::Result<Vec<PathBuf>> {
+    // `HwmonPwm::init` switches `pwmN_enable` to manual, so it must be
+    // there and writable.
+    let pwm = |dir: PathBuf, channel: u32, enable: bool| {
+        let mut out = vec![dir.join(format!("pwm{channel}"))];
+        if enable {
+            out.push(dir.join(format!("pwm{channel}_enable")));
+        }
+        out
+    };
+    Ok(match backend {
+        FanBackend::Sysfs { path } => vec![PathBuf::from(path)],
+        FanBackend::PwmChip { chip, channel, .. } => {
+            let dir = PathBuf::from(chip).join(format!("pwm{channel}"));
+            if dir.exists() {
+                ["period", "duty_cycle", "enable"]
+                    .iter()
+                    .map(|f| dir.join(f))
+                    .collect()
+            } else {
+                vec![PathBuf::from(chip).join("export")]
+            }
+        }
+        FanBackend::CrosEc { channel } => pwm(find_hwmon("cros_ec")?, *channel, true),
+        FanBackend::Hwmon { chip, channel } => pwm(find_hwmon(chip)?, *channel, true),
+        FanBackend::DellSmm { channel, .. } => {
+            let dir = find_hwmon("dell_smm")?;
+            let mut out = pwm(dir.clone(), *channel, false);
+            if dir.join("pwm1_enable").exists() {
+                out.push(dir.join("pwm1_enable"));
+            }
+            out
+        }
+        FanBackend::ThinkPad { path, .. } => vec![PathBuf::from(path)],
+        FanBackend::PwmFan => pwm(find_hwmon("pwmfan")?, 1, false),
+        FanBackend::GpioFan => pwm(find_hwmon("gpio_fan")?, 1, true),
+        FanBackend::CoolingDevice { device } => {
+            vec![find_cooling_device(device)?.join("cur_state")]
+        }
+        FanBackend::UsbHid { model, serial, .. } => {
+            vec![usb_hid::hidraw_node(*model, serial.as_deref())?]
+        }
+    })
+}
+
+fn find_hwmon(name: &str) -> io::Result<PathBuf> {
+    crate::find_hwmons_by_name(name)
+        .into_iter()
+        .next()
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            let msg = format!("hwmon '{name}' not found");
+            io::Error::new(io::ErrorKind::NotFound, msg)
+        })
+}
+
+/// Restricts another output to a few discrete duties. A request is rounded
+/// up to the next level, so the fan never runs slower than the curve asks;
+/// stepping back down waits until the request is `hysteresis` below the
+/// lower level, so a request hovering on a boundary does not toggle.
+pub struct LevelMapped {
+    inner: Box<dyn FanOutput>,
+    levels: Vec<i32>,
+    hysteresis: i32,
+    current: Option<usize>,
+    /// Last (requested, written) pair, so read-back reports the request when
+    /// the hardware holds the level we picked for it.
+    last: Option<(i32, i32)>,
+}
+
+impl LevelMapped {
+    pub fn new(inner: Box<dyn FanOutput>, levels: Vec<i32>, hysteresis: i32) -> Self {
+        Self {
+            inner,
+            levels,
+            hysteresis,
+            current: None,
+            last: None,
+        }
+    }
+
+    fn pick(&mut self, duty: i32) -> i32 {
+        let target = self
+            .levels
+            .iter()
+            .position(|&l| l >= duty)
+            .unwrap_or(self.levels.len() - 1);
+        let mut idx = match self.current {
+            Some(cur) if cur > target => cur,
+            _ => target,
+        };
+        // The bottom level must stay reachable even when the hysteresis
+        // would put its threshold below anything the curve can ask for.
+        while idx > target && duty <= (self.levels[idx - 1] - self.hysteresis).max(self.levels[0]) {
+            idx -= 1;
+        }
+        self.current = Some(idx);
+        self.levels[idx]
+    }
+}
+
+impl FanOutput for LevelMapped {
+    fn init(&mut self) -> io::Result<()> {
+        self.inner.init()
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        let level = self.pick(duty);
+        self.inner.write(level)?;
+        self.last = Some((duty, level));
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        let actual = self.inner.read()?;
+        match self.last {
+            Some((duty, level)) if level == actual => Some(duty),
+            _ => Some(actual),
+        }
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        self.inner.release()
+    }
+
+    fn force_manual(&mut self) -> io::Result<bool> {
+        self.inner.force_manual()
+    }
+
+    fn set_auto(&mut self) -> io::Result<bool> {
+        self.inner.set_auto()
+    }
+
+    fn describe(&self) -> String {
+        let levels: Vec<String> = self.levels.iter().map(|l| l.to_string()).collect();
+        format!("{} (levels {})", self.inner.describe(), levels.join("/"))
+    }
+}
+
+/// Translates requested percent into the percent actually written, along
+/// a piecewise-linear table, so that fans with very different duty-to-speed
+/// responses give roughly the airflow the curve asks for.
+pub struct Calibrated {
+    inner: Box<dyn FanOutput>,
+    table: Vec<(f64, i32)>,
+    /// Last (requested, written) pair, as in `LevelMapped`.
+    last: Option<(i32, i32)>,
+}
+
+impl Calibrated {
+    pub fn new(inner: Box<dyn FanOutput>, table: Vec<(f64, i32)>) -> Self {
+        Self {
+            inner,
+            table,
+            last: None,
+        }
+    }
+
+    /// Maps a written value back onto the requested scale.
+    fn invert(&self, raw: i32) -> i32 {
+        for w in self.table.windows(2) {
+            let ((t0, r0), (t1, r1)) = (w[0], w[1]);
+            if r1 > r0 && (r0..=r1).contains(&raw) {
+                let ratio = (raw - r0) as f64 / (r1 - r0) as f64;
+                return (t0 + ratio * (t1 - t0)).round() as i32;
+            }
+        }
+        if raw <= self.table[0].1 {
+            self.table[0].0.round() as i32
+        } else {
+            self.table[self.table.len() - 1].0.round() as i32
+        }
+    }
+}
+
+impl FanOutput for Calibrated {
+    fn init(&mut self) -> io::Result<()> {
+        self.inner.init()
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        let raw = crate::lerp_curve(duty as f64, &self.table);
+        self.inner.write(raw)?;
+        self.last = Some((duty, raw));
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        let actual = self.inner.read()?;
+        match self.last {
+            Some((duty, raw)) if raw == actual => Some(duty),
+            _ => Some(self.invert(actual)),
+        }
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        self.inner.release()
+    }
+
+    fn force_manual(&mut self) -> io::Result<bool> {
+        self.inner.force_manual()
+    }
+
+    fn set_auto(&mut self) -> io::Result<bool> {
+        self.inner.set_auto()
+    }
+
+    fn describe(&self) -> String {
+        format!("{} (calibrated)", self.inner.describe())
+    }
+}
+
+fn find_cooling_device(device: &str) -> io::Result<PathBuf> {
+    if device.starts_with('/') {
+        return Ok(PathBuf::from(device));
+    }
+    for entry in fs::read_dir("/sys/class/thermal")?.flatten() {
+        let dir = entry.path();
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with("cooling_device")
+        {
+            continue;
+        }
+        if let Ok(kind) = fs::read_to_string(dir.join("type")) {
+            if kind.trim() == device {
+                return Ok(dir);
+            }
+        }
+    }
+    let msg = format!("cooling device of type '{device}' not found");
+    Err(io::Error::new(io::ErrorKind::NotFound, msg))
+}
+
+struct SysfsDuty {
+    path: String,
+}
+
+impl FanOutput for SysfsDuty {
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        fs::write(&self.path, duty.to_string())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        fs::read_to_string(&self.path).ok()?.trim().parse().ok()
+    }
+
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+}
+
+struct PwmChip {
+    chip: PathBuf,
+    channel: u32,
+    period_ns: u64,
+    invert: bool,
+}
+
+impl PwmChip {
+    fn channel_dir(&self) -> PathBuf {
+        self.chip.join(format!("pwm{}", self.channel))
+    }
+
+    fn to_ns(&self, duty: i32) -> u64 {
+        let duty = duty.clamp(0, 100) as u64;
+        let duty = if self.invert { 100 - duty } else { duty };
+        self.period_ns * duty / 100
+    }
+}
+
+impl FanOutput for PwmChip {
+    fn init(&mut self) -> io::Result<()> {
+        let dir = self.channel_dir();
+        if !dir.exists() {
+            fs::write(self.chip.join("export"), self.channel.to_string())?;
+            // The channel directory shows up asynchronously and udev may
+            // still be fixing up its permissions.
+            for _ in 0..20 {
+                if fs::write(dir.join("duty_cycle"), "0").is_ok() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+        // duty_cycle must never exceed period, so drop it before changing
+        // the period.
+        fs::write(dir.join("duty_cycle"), "0")?;
+        fs::write(dir.join("period"), self.period_ns.to_string())?;
+        fs::write(dir.join("enable"), "1")
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        let ns = self.to_ns(duty);
+        fs::write(self.channel_dir().join("duty_cycle"), ns.to_string())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        let raw = fs::read_to_string(self.channel_dir().join("duty_cycle")).ok()?;
+        let ns: u64 = raw.trim().parse().ok()?;
+        if self.period_ns == 0 {
+            return None;
+        }
+        let duty = ((ns * 100) as f64 / self.period_ns as f64).round() as i32;
+        Some(if self.invert { 100 - duty } else { duty })
+    }
+
+    fn describe(&self) -> String {
+        self.channel_dir().to_string_lossy().to_string()
+    }
+}
+
+/// A standard hwmon `pwmN` attribute on the 0..=255 scale. `pwmN_enable` is
+/// switched to manual (1) on init; the original mode and, if it was already
+/// manual, the original duty are put back on release.
+struct HwmonPwm {
+    dir: PathBuf,
+    channel: u32,
+    /// Whether `pwmN_enable` means manual/auto. pwm-fan uses it for
+    /// something else (what pwm = 0 does to the regulator).
+    set_manual: bool,
+    /// Whether `pwmN` reads back what was written. gpio-fan reports the
+    /// speed step it picked instead.
+    verify: bool,
+    /// Whether `pwmN_enable` = 2 is the chip's automatic control. gpio-fan
+    /// only takes 0 (full speed) and 1 (manual).
+    has_auto: bool,
+    saved_enable: Option<String>,
+    saved_pwm: Option<String>,
+}
+
+impl HwmonPwm {
+    fn new(dir: PathBuf, channel: u32) -> Self {
+        Self {
+            dir,
+            channel,
+            set_manual: true,
+            verify: true,
+            has_auto: true,
+            saved_enable: None,
+            saved_pwm: None,
+        }
+    }
+
+    fn attr(&self, suffix: &str) -> PathBuf {
+        self.dir.join(format!("pwm{}{suffix}", self.channel))
+    }
+}
+
+impl FanOutput for HwmonPwm {
+    fn init(&mut self) -> io::Result<()> {
+        if !self.set_manual {
+            return Ok(());
+        }
+        let enable = fs::read_to_string(self.attr("_enable"))?.trim().to_string();
+        self.saved_pwm = fs::read_to_string(self.attr(""))
+            .ok()
+            .map(|v| v.trim().to_string());
+        self.saved_enable = Some(enable);
+        fs::write(self.attr("_enable"), "1")
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        let raw = (duty.clamp(0, 100) as f64 * 255.0 / 100.0).round() as i32;
+        fs::write(self.attr(""), raw.to_string())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        if !self.verify {
+            return None;
+        }
+        let raw: i32 = fs::read_to_string(self.attr(""))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some((raw as f64 * 100.0 / 255.0).round() as i32)
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        let Some(enable) = self.saved_enable.take() else {
+            return Ok(());
+        };
+        if enable == "1" {
+            if let Some(pwm) = &self.saved_pwm {
+                fs::write(self.attr(""), pwm)?;
+            }
+        }
+        fs::write(self.attr("_enable"), enable)
+    }
+
+    fn force_manual(&mut self) -> io::Result<bool> {
+        if !self.set_manual || fs::read_to_string(self.attr("_enable"))?.trim() == "1" {
+            return Ok(false);
+        }
+        fs::write(self.attr("_enable"), "1")?;
+        Ok(true)
+    }
+
+    fn set_auto(&mut self) -> io::Result<bool> {
+        if !self.set_manual || !self.has_auto {
+            return Ok(false);
+        }
+        fs::write(self.attr("_enable"), "2")?;
+        Ok(true)
+    }
+
+    fn describe(&self) -> String {
+        self.attr("").to_string_lossy().to_string()
+    }
+}
+
+struct DellSmm {
+    dir: PathBuf,
+    channel: u32,
+    states: u32,
+    /// Last (duty, state) written, so read-back compares fan states rather
+    /// than the percentages that were rounded onto them.
+    last: Option<(i32, u32)>,
+    saved_enable: Option<String>,
+}
+
+impl DellSmm {
+    fn pwm_path(&self) -> PathBuf {
+        self.dir.join(format!("pwm{}", self.channel))
+    }
+
+    /// `pwm1_enable` is global for all fans and only exists on machines
+    /// where the driver knows how to turn BIOS control off.
+    fn enable_path(&self) -> PathBuf {
+        self.dir.join("pwm1_enable")
+    }
+
+    fn max_state(&self) -> f64 {
+        (self.states - 1) as f64
+    }
+}
+
+impl FanOutput for DellSmm {
+    fn init(&mut self) -> io::Result<()> {
+        if let Ok(enable) = fs::read_to_string(self.enable_path()) {
+            fs::write(self.enable_path(), "1")?;
+            self.saved_enable = Some(enable.trim().to_string());
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        let state = (duty.clamp(0, 100) as f64 / 100.0 * self.max_state()).round() as u32;
+        let raw = (state as f64 * 255.0 / self.max_state()).round() as u32;
+        fs::write(self.pwm_path(), raw.to_string())?;
+        self.last = Some((duty, state));
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        let raw: u32 = fs::read_to_string(self.pwm_path())
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        let state = (raw as f64 / 255.0 * self.max_state()).round() as u32;
+        match self.last {
+            Some((duty, last_state)) if last_state == state => Some(duty),
+            _ => Some((state as f64 * 100.0 / self.max_state()).round() as i32),
+        }
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        match self.saved_enable.take() {
+            Some(enable) => fs::write(self.enable_path(), enable),
+            None => Ok(()),
+        }
+    }
+
+    fn force_manual(&mut self) -> io::Result<bool> {
+        if self.saved_enable.is_none() {
+            return Ok(false);
+        }
+        fs::write(self.enable_path(), "1")?;
+        Ok(true)
+    }
+
+    fn set_auto(&mut self) -> io::Result<bool> {
+        if self.saved_enable.is_none() {
+            return Ok(false);
+        }
+        fs::write(self.enable_path(), "2")?;
+        Ok(true)
+    }
+
+    fn describe(&self) -> String {
+        self.pwm_path().to_string_lossy().to_string()
+    }
+}
+
+struct ThinkPad {
+    path: String,
+    watchdog_sec: u32,
+    full_speed: bool,
+    last: Option<(i32, String)>,
+}
+
+impl ThinkPad {
+    fn level_for(&self, duty: i32) -> String {
+        let duty = duty.clamp(0, 100);
+        if duty == 100 && self.full_speed {
+            return "full-speed".to_string();
+        }
+        ((duty as f64 * 7.0 / 100.0).round() as i32).to_string()
+    }
+}
+
+impl FanOutput for ThinkPad {
+    /// Arms the firmware watchdog: if no level is written for
+    /// `watchdog_sec`, thinkpad_acpi puts the fan back into auto mode. Every
+    /// level write re-arms it, so `poll_sec` (or `reassert_sec`) must stay
+    /// below `watchdog_sec`.
+    fn init(&mut self) -> io::Result<()> {
+        fs::write(&self.path, format!("watchdog {}", self.watchdog_sec))
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        let level = self.level_for(duty);
+        fs::write(&self.path, format!("level {level}"))?;
+        self.last = Some((duty, level));
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        let raw = fs::read_to_string(&self.path).ok()?;
+        let level = raw
+            .lines()
+            .find_map(|l| l.strip_prefix("level:"))?
+            .trim()
+            .to_string();
+        match &self.last {
+            Some((duty, last)) if *last == level => Some(*duty),
+            _ if level == "full-speed" => Some(100),
+            // "auto" and "disengaged" have no duty equivalent.
+            _ => level
+                .parse::<i32>()
+                .ok()
+                .map(|l| (l as f64 * 100.0 / 7.0).round() as i32),
+        }
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        fs::write(&self.path, "level auto")?;
+        fs::write(&self.path, "watchdog 0")
+    }
+
+    /// The next level write takes the fan back, so there is nothing for
+    /// `force_manual` to do.
+    fn set_auto(&mut self) -> io::Result<bool> {
+        fs::write(&self.path, "level auto")?;
+        Ok(true)
+    }
+
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+}
+
+struct CoolingDevice {
+    dir: PathBuf,
+    max_state: u32,
+    last: Option<(i32, u32)>,
+}
+
+impl CoolingDevice {
+    fn read_state(&self, name: &str) -> io::Result<u32> {
+        fs::read_to_string(self.dir.join(name))?
+            .trim()
+            .parse()
+            .map_err(|_| io::ErrorKind::InvalidData.into())
+    }
+}
+
+impl FanOutput for CoolingDevice {
+    fn init(&mut self) -> io::Result<()> {
+        self.max_state = self.read_state("max_state")?;
+        if self.max_state == 0 {
+            let msg = format!("{} has max_state 0", self.dir.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        let state = (duty.clamp(0, 100) as f64 * self.max_state as f64 / 100.0).round() as u32;
+        fs::write(self.dir.join("cur_state"), state.to_string())?;
+        self.last = Some((duty, state));
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        let state = self.read_state("cur_state").ok()?;
+        match self.last {
+            Some((duty, last)) if last == state => Some(duty),
+            _ => Some((state as f64 * 100.0 / self.max_state as f64).round() as i32),
+        }
+    }
+
+    fn describe(&self) -> String {
+        self.dir.join("cur_state").to_string_lossy().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Hardware that holds whatever was last written, shared with the test.
+    struct Probe(Rc<Cell<Option<i32>>>);
+
+    impl FanOutput for Probe {
+        fn write(&mut self, duty: i32) -> io::Result<()> {
+            self.0.set(Some(duty));
+            Ok(())
+        }
+
+        fn read(&mut self) -> Option<i32> {
+            self.0.get()
+        }
+
+        fn describe(&self) -> String {
+            "probe".to_string()
+        }
+    }
+
+    fn probe() -> (Box<dyn FanOutput>, Rc<Cell<Option<i32>>>) {
+        let raw = Rc::new(Cell::new(None));
+        (Box::new(Probe(raw.clone())), raw)
+    }
+
+    #[test]
+    fn levels_round_up_and_step_down_past_the_hysteresis() {
+        let (inner, raw) = probe();
+        let mut fan = LevelMapped::new(inner, vec![20, 50, 80, 100], 5);
+        let mut write = |duty| {
+            fan.write(duty).unwrap();
+            raw.get().unwrap()
+        };
+        assert_eq!(write(60), 80);
+        assert_eq!(write(50), 80);
+        assert_eq!(write(46), 80);
+        assert_eq!(write(45), 50);
+        assert_eq!(write(51), 80);
+        assert_eq!(write(45), 50);
+        // The bottom level's threshold is the level itself, not 15.
+        assert_eq!(write(21), 50);
+        assert_eq!(write(20), 20);
+        assert_eq!(write(101), 100);
+    }
+
+    #[test]
+    fn levels_read_back_the_request_while_the_level_holds() {
+        let (inner, raw) = probe();
+        let mut fan = LevelMapped::new(inner, vec![20, 50, 80, 100], 5);
+        fan.write(60).unwrap();
+        assert_eq!(fan.read(), Some(60));
+        raw.set(Some(50));
+        assert_eq!(fan.read(), Some(50));
+    }
+
+    #[test]
+    fn calibration_interpolates_and_holds_the_table_ends() {
+        let (inner, raw) = probe();
+        let mut fan = Calibrated::new(inner, vec![(20.0, 30), (80.0, 90)]);
+        let mut write = |duty| {
+            fan.write(duty).unwrap();
+            raw.get().unwrap()
+        };
+        assert_eq!(write(50), 60);
+        assert_eq!(write(0), 30);
+        assert_eq!(write(100), 90);
+    }
+
+    #[test]
+    fn calibration_reads_back_onto_the_requested_scale() {
+        let (inner, raw) = probe();
+        let mut fan = Calibrated::new(inner, vec![(20.0, 30), (80.0, 90)]);
+        fan.write(10).unwrap();
+        assert_eq!(fan.read(), Some(10));
+        raw.set(Some(60));
+        assert_eq!(fan.read(), Some(50));
+        raw.set(Some(100));
+        assert_eq!(fan.read(), Some(80));
+        raw.set(Some(0));
+        assert_eq!(fan.read(), Some(20));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{status_path, unix_now, Config};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Cycles the loop may miss before it counts as stuck.
+const MISSED_CYCLES: f64 = 3.0;
+/// Floor on the allowed status age, for very short `poll_sec`.
+const MIN_AGE_SEC: f64 = 10.0;
+
+/// The parts of the published status the check looks at.
+#[derive(Deserialize)]
+struct Snapshot {
+    pid: u32,
+    updated_unix: u64,
+    mode: String,
+    last_error: Option<String>,
+    cpu_temp_c: Option<f64>,
+    mem_temp_c: Option<f64>,
+    #[serde(default)]
+    fans: Vec<FanSnapshot>,
+}
+
+#[derive(Deserialize)]
+struct FanSnapshot {
+    name: String,
+    duty: Option<i32>,
+    #[serde(default)]
+    firmware_auto: bool,
+}
+
+/// Checks the daemon through its status file: running, looping, not in
+/// failsafe and reading every sensor. Ok carries a one-line summary, Err
+/// the reasons it is unhealthy.
+pub fn check(cfg: &Config) -> Result<String, String> {
+    let path = status_path(cfg);
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read {path}: {e}; is the daemon running?"))?;
+    let status: Snapshot = toml::from_str(&raw).map_err(|e| format!("{path}: {e}"))?;
+    if !Path::new(&format!("/proc/{}", status.pid)).exists() {
+        return Err(format!("daemon (pid {}) is not running", status.pid));
+    }
+    let age = unix_now().saturating_sub(status.updated_unix);
+    let max_age = (cfg.poll_sec * MISSED_CYCLES).max(MIN_AGE_SEC);
+    if age as f64 > max_age {
+        return Err(format!("control loop last ran {age}s ago"));
+    }
+
+    let mut problems = Vec::new();
+    if status.mode == "failsafe" {
+        problems.push(match &status.last_error {
+            Some(e) => format!("failsafe: {e}"),
+            None => "failsafe".to_string(),
+        });
+    }
+    if status.mode == "critical" {
+        problems.push(match &status.last_error {
+            Some(e) => format!("above shutdown_c, failsafe: {e}"),
+            None => "above shutdown_c".to_string(),
+        });
+    }
+    // A failed cycle leaves the temperatures out and is reported above.
+    let failing =
+        status.mode == "failsafe" || (status.mode == "critical" && status.last_error.is_some());
+    for (zone, t) in [("cpu", status.cpu_temp_c), ("mem", status.mem_temp_c)] {
+        if t.is_none() && !failing {
+            problems.push(format!("{zone} sensor has no reading"));
+        }
+    }
+    for fan in &status.fans {
+        if fan.firmware_auto {
+            problems.push(format!("{} is under firmware control", fan.name));
+        } else if fan.duty.is_none() {
+            problems.push(format!("{} has not been written", fan.name));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(problems.join("; "));
+    }
+    let duties: Vec<String> = status
+        .fans
+        .iter()
+        .map(|f| format!("{} {}%", f.name, f.duty.unwrap_or(0)))
+        .collect();
+    Ok(format!(
+        "{}, updated {age}s ago, {}",
+        status.mode,
+        duties.join(", ")
+    ))
+}
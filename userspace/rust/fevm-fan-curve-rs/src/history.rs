@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// One control cycle: when it ran, the zone temperatures it saw and the
+/// duty each fan ended up with.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub unix: f64,
+    pub mode: String,
+    /// Whether the cycle was in failsafe, which `mode` shows as "critical"
+    /// while above `shutdown_c`.
+    pub failsafe: bool,
+    pub temps: Vec<Option<f64>>,
+    pub duties: Vec<Option<i32>>,
+}
+
+/// Bounded in-memory record of recent samples, oldest first.
+pub struct History {
+    zones: Vec<String>,
+    fans: Vec<String>,
+    capacity: usize,
+    samples: VecDeque<Sample>,
+}
+
+impl History {
+    pub fn new(zones: Vec<String>, fans: Vec<String>, capacity: usize) -> Self {
+        Self {
+            zones,
+            fans,
+            capacity: capacity.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Reads back what `to_csv` wrote (or `export` printed), so a recorded
+    /// trace can be plotted or replayed.
+    pub fn from_csv(csv: &str) -> Result<Self, String> {
+        let mut lines = csv
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty());
+        let (_, header) = lines.next().ok_or("empty trace")?;
+        let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+        if columns.len() < 2 || columns[0] != "unix" || columns[1] != "mode" {
+            return Err("trace header must start with unix,mode".to_string());
+        }
+        let zone_count = columns[2..]
+            .iter()
+            .take_while(|c| c.ends_with("_c"))
+            .count();
+        let zones: Vec<String> = columns[2..2 + zone_count]
+            .iter()
+            .map(|c| c.trim_end_matches("_c").to_string())
+            .collect();
+        let fans: Vec<String> = columns[2 + zone_count..]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+
+        let mut samples = VecDeque::new();
+        for (n, line) in lines {
+            let bad = |what: &str| format!("line {}: {what}", n + 1);
+            let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+            if cells.len() != columns.len() {
+                return Err(bad(&format!(
+                    "{} columns, header has {}",
+                    cells.len(),
+                    columns.len()
+                )));
+            }
+            let unix = cells[0].parse().map_err(|_| bad("bad unix time"))?;
+            let temps = cells[2..2 + zone_count]
+                .iter()
+                .map(|c| match *c {
+                    "" => Ok(None),
+                    c => c.parse().map(Some).map_err(|_| bad("bad temperature")),
+                })
+                .collect::<Result<_, _>>()?;
+            let duties = cells[2 + zone_count..]
+                .iter()
+                .map(|c| match *c {
+                    "" => Ok(None),
+                    c => c.parse().map(Some).map_err(|_| bad("bad duty")),
+                })
+                .collect::<Result<_, _>>()?;
+            samples.push_back(Sample {
+                unix,
+                mode: cells[1].to_string(),
+                failsafe: cells[1] == "failsafe",
+                temps,
+                duties,
+            });
+        }
+        Ok(Self {
+            zones,
+            fans,
+            capacity: samples.len().max(1),
+            samples,
+        })
+    }
+
+    pub fn zones(&self) -> &[String] {
+        &self.zones
+    }
+
+    pub fn fans(&self) -> &[String] {
+        &self.fans
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    /// CSV with a header row: `unix,mode,<zone>_c...,<fan>...`. Missing
+    /// values are left empty.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("unix,mode");
+        for z in &self.zones {
+            let _ = write!(out, ",{z}_c");
+        }
+        for f in &self.fans {
+            let _ = write!(out, ",{f}");
+        }
+        out.push('\n');
+
+        for s in &self.samples {
+            let _ = write!(out, "{:.3},{}", s.unix, s.mode);
+            for t in &s.temps {
+                match t {
+                    Some(t) => {
+                        let _ = write!(out, ",{t:.1}");
+                    }
+                    None => out.push(','),
+                }
+            }
+            for d in &s.duties {
+                match d {
+                    Some(d) => {
+                        let _ = write!(out, ",{d}");
+                    }
+                    None => out.push(','),
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
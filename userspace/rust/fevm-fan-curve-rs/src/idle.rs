@@ -0,0 +1,84 @@
+use crate::Quiet;
+use std::process::Command;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// How often logind is asked.
+const POLL_SEC: f64 = 10.0;
+
+/// Follows systemd-logind's idle hint, which is set once every session
+/// has gone idle (or there is none), to decide when the quiet profile
+/// applies.
+pub struct Idle {
+    last_poll: Option<Instant>,
+    quiet: bool,
+}
+
+/// A property of the logind manager object, as busctl prints it, e.g.
+/// "b true" or "t 1700000000000000".
+fn manager_property(name: &str) -> Result<String, String> {
+    let out = Command::new("busctl")
+        .args([
+            "get-property",
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+            name,
+        ])
+        .output()
+        .map_err(|e| format!("cannot run busctl: {e}"))?;
+    if !out.status.success() {
+        return Err(format!(
+            "busctl get-property {name}: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+impl Idle {
+    /// Starts in the quiet profile when the previous run was in it, until
+    /// the first answer from logind.
+    pub fn new(quiet: bool) -> Self {
+        Self {
+            last_poll: None,
+            quiet,
+        }
+    }
+
+    pub fn quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Asks logind every `POLL_SEC`. When it cannot be asked the normal
+    /// profile is used.
+    pub fn update(&mut self, quiet: &Quiet) -> Result<(), String> {
+        if self
+            .last_poll
+            .is_some_and(|t| t.elapsed().as_secs_f64() < POLL_SEC)
+        {
+            return Ok(());
+        }
+        self.last_poll = Some(Instant::now());
+        let result = Self::idle_for(quiet.idle_sec);
+        self.quiet = result.as_ref().is_ok_and(|idle| *idle);
+        result.map(|_| ())
+    }
+
+    /// Whether every session has been idle for at least `idle_sec`.
+    fn idle_for(idle_sec: f64) -> Result<bool, String> {
+        if manager_property("IdleHint")? != "b true" {
+            return Ok(false);
+        }
+        let since = manager_property("IdleSinceHint")?;
+        let since_us: u64 = since
+            .strip_prefix("t ")
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| format!("unexpected IdleSinceHint: {since}"))?;
+        // 0 means logind does not know since when; take it as long ago.
+        let now_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        Ok(now_us.saturating_sub(since_us) as f64 >= idle_sec * 1e6)
+    }
+}
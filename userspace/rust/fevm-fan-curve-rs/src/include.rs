@@ -0,0 +1,268 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Deepest chain of includes followed, which also stops include cycles.
+const MAX_DEPTH: usize = 8;
+
+/// Reads a config file and everything it pulls in with a top-level
+/// `include = "path"` or `include = ["a", "b"]`.
+///
+/// Paths are relative to the including file; `*` and `?` in the file name
+/// match like a shell glob, in sorted order, and may match nothing. Each
+/// included file is merged over the including one: tables merge key by
+/// key, arrays of tables (`[[fans]]`) are appended, and anything else is
+/// replaced.
+pub fn load(path: &Path) -> Result<Table, Box<dyn std::error::Error>> {
+    load_at(path, 0)
+}
+
+fn load_at(path: &Path, depth: usize) -> Result<Table, Box<dyn std::error::Error>> {
+    if depth > MAX_DEPTH {
+        return Err(format!("{}: includes nested too deep (cycle?)", path.display()).into());
+    }
+    let raw = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut table: Table = toml::from_str(&raw).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let patterns = match table.remove("include") {
+        None => Vec::new(),
+        Some(Value::String(s)) => vec![s],
+        Some(Value::Array(items)) => items
+            .into_iter()
+            .map(|v| match v {
+                Value::String(s) => Ok(s),
+                _ => Err(format!(
+                    "{}: include entries must be strings",
+                    path.display()
+                )),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(format!("{}: include must be a string or a list", path.display()).into())
+        }
+    };
+    // Anywhere else it would be dropped silently as an unknown key.
+    if let Some(key) = nested_include(&table, "") {
+        return Err(format!(
+            "{}: {key}: include is only allowed at the top level",
+            path.display()
+        )
+        .into());
+    }
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    for pattern in patterns {
+        for file in expand(&base.join(&pattern))? {
+            let fragment = load_at(&file, depth + 1)?;
+            merge(&mut table, fragment);
+        }
+    }
+    Ok(table)
+}
+
+/// The dotted path of an `include` key inside `table`'s tables or arrays
+/// of tables, if there is one.
+fn nested_include(table: &Table, prefix: &str) -> Option<String> {
+    for (key, value) in table {
+        let path = format!("{prefix}{key}");
+        if !prefix.is_empty() && key == "include" {
+            return Some(path);
+        }
+        let found = match value {
+            Value::Table(t) => nested_include(t, &format!("{path}.")),
+            Value::Array(items) => items.iter().find_map(|v| match v {
+                Value::Table(t) => nested_include(t, &format!("{path}.")),
+                _ => None,
+            }),
+            _ => None,
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+/// Files matching a path whose last component may contain `*` or `?`. A
+/// plain path must exist.
+fn expand(pattern: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let name = pattern
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        if !pattern.exists() {
+            return Err(format!("include {}: no such file", pattern.display()).into());
+        }
+        return Ok(vec![pattern.to_path_buf()]);
+    }
+    let dir = pattern.parent().unwrap_or(Path::new("."));
+    let mut out: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|e| {
+                let file = e.file_name().to_string_lossy().to_string();
+                // Like a shell, `*` does not pick up hidden files.
+                (name.starts_with('.') || !file.starts_with('.')) && wildcard(&name, &file)
+            })
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    out.sort();
+    Ok(out)
+}
+
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // Greedy match with backtracking to the last `*`.
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(b)), Value::Table(o)) => merge(b, o),
+            (Some(Value::Array(b)), Value::Array(o))
+                if !o.is_empty() && b.iter().chain(&o).all(Value::is_table) =>
+            {
+                b.extend(o)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(src: &str) -> Table {
+        toml::from_str(src).unwrap()
+    }
+
+    /// A fresh directory holding `files`.
+    fn dir_with(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fevm-include-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard("*.toml", "a.toml"));
+        assert!(wildcard("*.toml", ".toml"));
+        assert!(!wildcard("*.toml", "a.toml.bak"));
+        assert!(wildcard("fan?.toml", "fan1.toml"));
+        assert!(!wildcard("fan?.toml", "fan12.toml"));
+        assert!(wildcard("a*b*c", "aXbYbZc"));
+        assert!(!wildcard("a*b*c", "aXbY"));
+        assert!(wildcard("*", ""));
+    }
+
+    #[test]
+    fn merge_tables_arrays_of_tables_and_values() {
+        let mut base = table(
+            "[general]\npoll_sec = 1\nmin_duty = 20\n\
+             [sensors]\ncpu_names = [\"k10temp\"]\n\
+             [[fans]]\nname = \"fan1\"\n",
+        );
+        merge(
+            &mut base,
+            table(
+                "[general]\npoll_sec = 2\n\
+                 [sensors]\ncpu_names = [\"coretemp\"]\n\
+                 [[fans]]\nname = \"extra\"\n",
+            ),
+        );
+        assert_eq!(
+            base,
+            table(
+                "[general]\npoll_sec = 2\nmin_duty = 20\n\
+                 [sensors]\ncpu_names = [\"coretemp\"]\n\
+                 [[fans]]\nname = \"fan1\"\n\
+                 [[fans]]\nname = \"extra\"\n",
+            )
+        );
+    }
+
+    #[test]
+    fn nested_include_is_found() {
+        assert_eq!(nested_include(&table("include = \"x\""), ""), None);
+        assert_eq!(
+            nested_include(&table("[general]\ninclude = \"x\""), ""),
+            Some("general.include".to_string())
+        );
+        assert_eq!(
+            nested_include(&table("[[fans]]\nname = \"a\"\ninclude = \"x\""), ""),
+            Some("fans.include".to_string())
+        );
+    }
+
+    #[test]
+    fn load_follows_globs_in_order() {
+        let dir = dir_with(
+            "glob",
+            &[
+                (
+                    "main.toml",
+                    "include = [\"conf.d/*.toml\"]\n[general]\npoll_sec = 1\n",
+                ),
+                ("conf.d/20-b.toml", "[general]\npoll_sec = 3\n"),
+                (
+                    "conf.d/10-a.toml",
+                    "[general]\npoll_sec = 2\nmin_duty = 5\n",
+                ),
+                ("conf.d/.hidden.toml", "[general]\npoll_sec = 9\n"),
+            ],
+        );
+        let loaded = load(&dir.join("main.toml")).unwrap();
+        assert_eq!(loaded, table("[general]\npoll_sec = 3\nmin_duty = 5\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_errors() {
+        let dir = dir_with(
+            "errors",
+            &[
+                ("missing.toml", "include = \"nope.toml\"\n"),
+                ("cycle.toml", "include = \"cycle.toml\"\n"),
+                ("nested.toml", "[general]\ninclude = \"x.toml\"\n"),
+                ("empty-glob.toml", "include = \"none/*.toml\"\n"),
+            ],
+        );
+        let err = |file: &str| load(&dir.join(file)).unwrap_err().to_string();
+        assert!(err("missing.toml").ends_with("nope.toml: no such file"));
+        assert!(err("cycle.toml").ends_with("includes nested too deep (cycle?)"));
+        assert!(err("nested.toml")
+            .ends_with("general.include: include is only allowed at the top level"));
+        assert_eq!(load(&dir.join("empty-glob.toml")).unwrap(), Table::new());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
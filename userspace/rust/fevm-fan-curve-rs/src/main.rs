@@ -1,10 +1,61 @@
-use serde::Deserialize;
+/// Logs a line to stderr (the journal) and, when configured, to syslog.
+macro_rules! log_at {
+    ($severity:ident, $($arg:tt)*) => {{
+        let msg = format!($($arg)*);
+        eprintln!("{msg}");
+        $crate::syslog::send($crate::syslog::Severity::$severity, &msg);
+    }};
+}
+
+mod access;
+mod ambient;
+mod api;
+mod archive;
+mod bench;
+mod conflict;
+mod critical;
+mod detect;
+mod dust;
+mod escalate;
+mod expr;
+mod fan;
+mod health;
+mod history;
+mod idle;
+mod include;
+mod nvml_sensor;
+mod plot;
+mod power_supply;
+mod replay;
+mod resume;
+mod script;
+mod selftest;
+mod sensor;
+mod simulate;
+mod sqlite_log;
+mod stats;
+mod syslog;
+mod throttle;
+mod usb_hid;
+mod wasm_sensor;
+mod watchdog;
+
+use archive::Archive;
+use fan::{FanBackend, FanOutput};
+use history::{History, Sample};
+use sensor::SensorSource;
+use serde::{Deserialize, Serialize};
+use sqlite_log::SqliteLog;
+use stats::Stats;
+use std::collections::{BTreeMap, VecDeque};
 use std::env;
-use std::fs;
-use std::io;
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 type Curve = Vec<(f64, i32)>;
 
@@ -16,111 +67,493 @@ struct FileConfig {
     sensors: Sensors,
     #[serde(default)]
     curves: Curves,
+    #[serde(default)]
+    fans: Vec<FanFile>,
+    #[serde(default)]
+    zones: BTreeMap<String, ZoneFile>,
+    quiet: Option<QuietFile>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 struct General {
     fan1_path: Option<String>,
     fan2_path: Option<String>,
     poll_sec: Option<f64>,
+    sample_sec: Option<f64>,
     min_duty: Option<i32>,
     max_duty: Option<i32>,
     failsafe_duty: Option<i32>,
+    duty_rise_sec: Option<f64>,
+    duty_fall_sec: Option<f64>,
+    failsafe_hold_sec: Option<f64>,
+    failsafe_dwell_sec: Option<f64>,
+    fan_stop_dwell_sec: Option<f64>,
+    failsafe_ramp_sec: Option<f64>,
+    failsafe_escalate_sec: Option<f64>,
+    failsafe_rediscover: Option<bool>,
+    failsafe_rebind: Option<Vec<String>>,
+    alert_command: Option<String>,
+    watchdog: Option<String>,
+    throttle_bias: Option<i32>,
+    sync_fans: Option<bool>,
+    shutdown_c: Option<f64>,
+    shutdown_sec: Option<f64>,
+    shutdown_action: Option<String>,
+    shutdown_command: Option<String>,
+    ambient_reference: Option<f64>,
+    ambient_sensor: Option<String>,
+    ambient_max_shift: Option<f64>,
+    throttle_hold_sec: Option<f64>,
+    dust_clean_interval_h: Option<f64>,
+    dust_clean_sec: Option<f64>,
+    dust_clean_max_temp: Option<f64>,
+    dust_clean_max_load: Option<f64>,
+    readback_retries: Option<u32>,
+    write_retries: Option<u32>,
+    write_retry_delay_ms: Option<u64>,
+    runtime_dir: Option<String>,
+    history_sec: Option<f64>,
+    state_dir: Option<String>,
+    stats_thresholds: Option<Vec<f64>>,
+    stats_keep_days: Option<usize>,
+    sqlite_path: Option<String>,
+    sqlite_keep_days: Option<f64>,
+    archive: Option<bool>,
+    script: Option<String>,
+    syslog: Option<String>,
+    syslog_facility: Option<String>,
 }
 
-impl Default for General {
-    fn default() -> Self {
-        Self {
-            fan1_path: None,
-            fan2_path: None,
-            poll_sec: None,
-            min_duty: None,
-            max_duty: None,
-            failsafe_duty: None,
-        }
-    }
-}
-
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 struct Sensors {
     cpu_names: Option<Vec<String>>,
+    battery_names: Option<Vec<String>>,
     mem_names: Option<Vec<String>>,
     mem_fallback_to_cpu: Option<bool>,
+    #[serde(default)]
+    plugins: Vec<PluginFile>,
+    #[serde(default)]
+    nvml: Vec<NvmlFile>,
 }
 
-impl Default for Sensors {
-    fn default() -> Self {
+#[derive(Debug, Deserialize)]
+struct NvmlFile {
+    name: String,
+    zone: Option<String>,
+    gpu: Option<nvml_sensor::GpuId>,
+}
+
+/// An NVIDIA GPU feeding one zone.
+#[derive(Debug, Clone)]
+struct NvmlConfig {
+    name: String,
+    zone: String,
+    gpu: nvml_sensor::GpuId,
+}
+
+#[derive(Debug, Deserialize)]
+struct PluginFile {
+    name: String,
+    zone: Option<String>,
+    path: String,
+    config: Option<String>,
+    #[serde(default)]
+    allow_paths: Vec<String>,
+}
+
+/// A WASM sensor plugin feeding one zone.
+#[derive(Debug, Clone)]
+struct PluginConfig {
+    name: String,
+    zone: String,
+    path: String,
+    config: String,
+    allow_paths: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct Curves {
+    cpu: Option<CurveFile>,
+    mem: Option<CurveFile>,
+    battery: Option<CurveFile>,
+    system: Option<CurveFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuietFile {
+    idle_sec: Option<f64>,
+    cpu: Option<CurveFile>,
+    mem: Option<CurveFile>,
+}
+
+/// `[[temp, duty], ...]` points, or an expression of `t` as a string.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CurveFile {
+    Points(Vec<(f64, i32)>),
+    Expr(String),
+}
+
+impl CurveFile {
+    fn parse(self, key: &str) -> Result<ZoneCurve, String> {
+        match self {
+            Self::Points(points) => Ok(ZoneCurve::Points(points)),
+            Self::Expr(src) => expr::Expr::parse(&src)
+                .map(ZoneCurve::Expr)
+                .map_err(|e| format!("{key}: {e}")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct ZoneFile {
+    predict_sec: Option<f64>,
+    predict_samples: Option<usize>,
+    range: Option<(f64, f64)>,
+    combine: Option<String>,
+    weights: Option<BTreeMap<String, f64>>,
+}
+
+#[derive(Debug, Clone)]
+struct ZoneConfig {
+    name: String,
+    /// How far ahead to extrapolate the temperature; 0 disables prediction.
+    predict_sec: f64,
+    predict_samples: usize,
+    /// Temperatures scored 0 and 100 for the system zone; None for the
+    /// ends of the zone's own curve.
+    range: Option<(f64, f64)>,
+}
+
+impl ZoneConfig {
+    fn new(name: &str) -> Self {
         Self {
-            cpu_names: None,
-            mem_names: None,
-            mem_fallback_to_cpu: None,
+            name: name.to_string(),
+            predict_sec: 0.0,
+            predict_samples: 10,
+            range: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combine {
+    /// The hottest zone, after weighting, sets the score.
+    Max,
+    /// Weighted scores add up, so two warm zones count as much as one hot.
+    Sum,
+}
+
+/// A zone derived from the others: each zone temperature is scored 0..100
+/// across its range, weighted, and combined into one thermal pressure
+/// score that `curves.system` maps to a duty.
+#[derive(Debug, Clone)]
+struct SystemZone {
+    combine: Combine,
+    weights: Vec<(String, f64)>,
+    curve: ZoneCurve,
+}
+
+/// The quieter profile used while no user session is active.
+#[derive(Debug)]
+struct Quiet {
+    /// Seconds logind must have reported every session idle.
+    idle_sec: f64,
+    /// Curves replacing `curves.cpu`/`curves.mem`; None keeps the normal one.
+    cpu_curve: Option<ZoneCurve>,
+    mem_curve: Option<ZoneCurve>,
+}
+
 #[derive(Debug, Deserialize)]
-struct Curves {
-    cpu: Option<Vec<(f64, i32)>>,
-    mem: Option<Vec<(f64, i32)>>,
+struct FanFile {
+    name: String,
+    enabled: Option<bool>,
+    zone: Option<String>,
+    backend: Option<String>,
+    path: Option<String>,
+    chip: Option<String>,
+    channel: Option<u32>,
+    period_ns: Option<u64>,
+    invert: Option<bool>,
+    states: Option<u32>,
+    watchdog_sec: Option<u32>,
+    full_speed: Option<bool>,
+    device: Option<String>,
+    reassert_sec: Option<f64>,
+    levels: Option<Vec<i32>>,
+    level_hysteresis: Option<i32>,
+    rpm_path: Option<String>,
+    calibration: Option<Vec<(f64, i32)>>,
+    failsafe: Option<String>,
+    failsafe_ramp_up_sec: Option<f64>,
+    serial: Option<String>,
 }
 
-impl Default for Curves {
-    fn default() -> Self {
-        Self { cpu: None, mem: None }
+/// What a fan does while the loop is failing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FailsafeStrategy {
+    /// `failsafe_duty`, raised by escalation.
+    Duty,
+    /// The last duty written before the failure.
+    Hold,
+    /// From the last duty up to `max_duty` over `sec`.
+    Ramp { sec: f64 },
+    /// The firmware's own automatic control, where the output has one.
+    Auto,
+}
+
+#[derive(Debug, Clone)]
+struct FanConfig {
+    name: String,
+    zone: String,
+    backend: FanBackend,
+    reassert_sec: Option<f64>,
+    /// Discrete duties the fan is restricted to; empty for continuous.
+    levels: Vec<i32>,
+    level_hysteresis: i32,
+    /// Tachometer input (e.g. hwmon `fanN_input`), used by `selftest`.
+    rpm_path: Option<String>,
+    /// Requested percent to written percent; empty for none.
+    calibration: Curve,
+    failsafe: FailsafeStrategy,
+}
+
+impl FanConfig {
+    fn new(name: &str, zone: &str, backend: FanBackend) -> Self {
+        Self {
+            name: name.to_string(),
+            zone: zone.to_string(),
+            backend,
+            reassert_sec: None,
+            levels: Vec::new(),
+            level_hysteresis: 5,
+            rpm_path: None,
+            calibration: Vec::new(),
+            failsafe: FailsafeStrategy::Duty,
+        }
     }
 }
 
 #[derive(Debug)]
 struct Config {
-    fan1_path: String,
-    fan2_path: String,
+    fans: Vec<FanConfig>,
+    zones: Vec<ZoneConfig>,
     poll_sec: f64,
+    /// Temperatures are read this often between fan updates and averaged
+    /// (or fed to the predictor); None reads them once per `poll_sec`.
+    sample_sec: Option<f64>,
     min_duty: i32,
     max_duty: i32,
     failsafe_duty: i32,
+    /// Time constant of duty increases toward the curve; 0 is immediate.
+    duty_rise_sec: f64,
+    /// Time constant of duty decreases, usually much longer than the rise.
+    duty_fall_sec: f64,
+    /// Error-free time needed before failsafe is left, so a sensor failing
+    /// every few cycles stays one failsafe episode instead of many.
+    failsafe_dwell_sec: f64,
+    /// Shortest time a fan stays stopped, or running, before the curve may
+    /// start or stop it again. Only matters with `min_duty = 0`.
+    fan_stop_dwell_sec: f64,
+    /// How long `failsafe_duty` is kept after the error that caused it clears.
+    failsafe_hold_sec: f64,
+    /// How long the duty then takes to come down from there to the curve.
+    failsafe_ramp_sec: f64,
+    /// Seconds of unbroken failsafe per escalation level; 0 disables.
+    failsafe_escalate_sec: f64,
+    /// Look up sensors again at each escalation level. Outputs are only
+    /// reopened after a rebind.
+    failsafe_rediscover: bool,
+    /// Devices (`/sys/bus/*/drivers/<driver>/<device>`) rebound from level 2.
+    failsafe_rebind: Vec<String>,
+    /// Shell command run at each escalation level and on recovery.
+    alert_command: Option<String>,
+    /// Watchdog device petted every cycle, e.g. /dev/watchdog; None for off.
+    watchdog: Option<String>,
+    /// Duty added to cpu and system fans while the CPU throttles; 0 is off.
+    throttle_bias: i32,
+    /// Drive every fan at the highest target among them.
+    sync_fans: bool,
+    /// The idle profile, from `[quiet]`; None when not configured.
+    quiet: Option<Quiet>,
+    /// Temperature above which fans are forced to `max_duty` and, after
+    /// `shutdown_sec`, `shutdown_action` runs; None for off.
+    shutdown_c: Option<f64>,
+    shutdown_sec: f64,
+    /// "poweroff", "suspend" or "command" (runs `shutdown_command`).
+    shutdown_action: String,
+    shutdown_command: Option<String>,
+    /// Ambient the curves were written for; None turns compensation off.
+    ambient_reference: Option<f64>,
+    /// Hwmon selector or file to read ambient from; None estimates it.
+    ambient_sensor: Option<String>,
+    /// Largest shift, either way, applied to the cpu and mem curves. A hot
+    /// room's shift tapers off over the last segment of a curve and leaves
+    /// expression curves alone, so the duty still reaches the top.
+    ambient_max_shift: f64,
+    /// How long the bias stays after the last sign of throttling.
+    throttle_hold_sec: f64,
+    /// Hours between dust-clearing spin-ups; 0 disables them.
+    dust_clean_interval_h: f64,
+    /// How long each spin-up runs the fans at `max_duty`.
+    dust_clean_sec: f64,
+    /// A spin-up waits until every zone is at or below this...
+    dust_clean_max_temp: f64,
+    /// ...and the 1-minute load average is at or below this.
+    dust_clean_max_load: f64,
+    readback_retries: u32,
+    write_retries: u32,
+    write_retry_delay_ms: u64,
+    runtime_dir: String,
+    history_sec: f64,
+    state_dir: String,
+    stats_thresholds: Vec<f64>,
+    stats_keep_days: usize,
+    sqlite_path: Option<String>,
+    sqlite_keep_days: f64,
+    archive: bool,
+    script: Option<String>,
+    /// `local`, `udp://host:port` or `tcp://host:port`; None for stderr only.
+    syslog: Option<String>,
+    syslog_facility: String,
     cpu_sensor_names: Vec<String>,
+    /// Power supplies (BAT0, ACAD, ...) whose temperature drives
+    /// `battery_curve`; empty for none.
+    battery_sensor_names: Vec<String>,
     mem_sensor_names: Vec<String>,
     mem_fallback_to_cpu: bool,
-    cpu_curve: Curve,
-    mem_curve: Curve,
+    sensor_plugins: Vec<PluginConfig>,
+    nvml_sensors: Vec<NvmlConfig>,
+    cpu_curve: ZoneCurve,
+    mem_curve: ZoneCurve,
+    /// Applied to every fan as a floor while a battery sensor is present.
+    battery_curve: ZoneCurve,
+    system: Option<SystemZone>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            fan1_path: "/sys/devices/platform/fevm-ip3-wmi/fan1_duty".to_string(),
-            fan2_path: "/sys/devices/platform/fevm-ip3-wmi/fan2_duty".to_string(),
+            fans: vec![
+                FanConfig::new(
+                    "fan1",
+                    "cpu",
+                    FanBackend::Sysfs {
+                        path: "/sys/devices/platform/fevm-ip3-wmi/fan1_duty".to_string(),
+                    },
+                ),
+                FanConfig::new(
+                    "fan2",
+                    "mem",
+                    FanBackend::Sysfs {
+                        path: "/sys/devices/platform/fevm-ip3-wmi/fan2_duty".to_string(),
+                    },
+                ),
+            ],
+            zones: vec![
+                ZoneConfig::new("cpu"),
+                ZoneConfig::new("mem"),
+                ZoneConfig::new("battery"),
+            ],
             poll_sec: 1.0,
+            sample_sec: None,
             min_duty: 20,
             max_duty: 100,
             failsafe_duty: 70,
+            duty_rise_sec: 0.0,
+            duty_fall_sec: 0.0,
+            failsafe_hold_sec: 30.0,
+            failsafe_dwell_sec: 10.0,
+            fan_stop_dwell_sec: 30.0,
+            failsafe_ramp_sec: 30.0,
+            failsafe_escalate_sec: 120.0,
+            failsafe_rediscover: true,
+            failsafe_rebind: Vec::new(),
+            alert_command: None,
+            watchdog: None,
+            throttle_bias: 0,
+            sync_fans: false,
+            quiet: None,
+            shutdown_c: None,
+            shutdown_sec: 30.0,
+            shutdown_action: "poweroff".to_string(),
+            shutdown_command: None,
+            ambient_reference: None,
+            ambient_sensor: None,
+            ambient_max_shift: 5.0,
+            throttle_hold_sec: 30.0,
+            dust_clean_interval_h: 0.0,
+            dust_clean_sec: 30.0,
+            dust_clean_max_temp: 45.0,
+            dust_clean_max_load: 0.5,
+            readback_retries: 2,
+            write_retries: 2,
+            write_retry_delay_ms: 50,
+            runtime_dir: "/run/fevm-fan-curve".to_string(),
+            history_sec: 1800.0,
+            state_dir: "/var/lib/fevm-fan-curve".to_string(),
+            stats_thresholds: vec![70.0, 80.0, 90.0],
+            stats_keep_days: 400,
+            sqlite_path: None,
+            sqlite_keep_days: 30.0,
+            archive: true,
+            script: None,
+            syslog: None,
+            syslog_facility: "daemon".to_string(),
             cpu_sensor_names: vec!["k10temp".to_string()],
+            battery_sensor_names: Vec::new(),
             mem_sensor_names: vec!["spd5118".to_string()],
             mem_fallback_to_cpu: true,
-            cpu_curve: vec![(40.0, 20), (55.0, 35), (65.0, 55), (75.0, 75), (85.0, 100)],
-            mem_curve: vec![(35.0, 20), (50.0, 40), (60.0, 60), (70.0, 80), (80.0, 100)],
+            sensor_plugins: Vec::new(),
+            nvml_sensors: Vec::new(),
+            cpu_curve: ZoneCurve::Points(vec![
+                (40.0, 20),
+                (55.0, 35),
+                (65.0, 55),
+                (75.0, 75),
+                (85.0, 100),
+            ]),
+            mem_curve: ZoneCurve::Points(vec![
+                (35.0, 20),
+                (50.0, 40),
+                (60.0, 60),
+                (70.0, 80),
+                (80.0, 100),
+            ]),
+            // Gentle: a cell warm from fast charging only needs some airflow.
+            battery_curve: ZoneCurve::Points(vec![(35.0, 0), (45.0, 30), (55.0, 60)]),
+            system: None,
         }
     }
 }
 
-fn load_config(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
+/// Loads `path` over the defaults. A named instance gets its own runtime
+/// and state directories by default so instances never share a socket,
+/// lock or statistics.
+fn load_config(path: &str, instance: Option<&str>) -> Result<Config, Box<dyn std::error::Error>> {
     let mut cfg = Config::default();
+    if let Some(name) = instance {
+        cfg.runtime_dir = format!("{}/{name}", cfg.runtime_dir);
+        cfg.state_dir = format!("{}/{name}", cfg.state_dir);
+    }
     if !Path::new(path).exists() {
         return Ok(cfg);
     }
 
-    let raw = fs::read_to_string(path)?;
-    let file_cfg: FileConfig = toml::from_str(&raw)?;
+    let file_cfg: FileConfig = toml::Value::Table(include::load(Path::new(path))?).try_into()?;
 
     if let Some(v) = file_cfg.general.fan1_path {
-        cfg.fan1_path = v;
+        cfg.fans[0].backend = FanBackend::Sysfs { path: v };
     }
     if let Some(v) = file_cfg.general.fan2_path {
-        cfg.fan2_path = v;
+        cfg.fans[1].backend = FanBackend::Sysfs { path: v };
     }
     if let Some(v) = file_cfg.general.poll_sec {
         cfg.poll_sec = v;
     }
+    if let Some(v) = file_cfg.general.sample_sec {
+        cfg.sample_sec = Some(v);
+    }
     if let Some(v) = file_cfg.general.min_duty {
         cfg.min_duty = v;
     }
@@ -130,27 +563,678 @@ fn load_config(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
     if let Some(v) = file_cfg.general.failsafe_duty {
         cfg.failsafe_duty = v;
     }
+    if let Some(v) = file_cfg.general.duty_rise_sec {
+        cfg.duty_rise_sec = v;
+    }
+    if let Some(v) = file_cfg.general.duty_fall_sec {
+        cfg.duty_fall_sec = v;
+    }
+    if let Some(v) = file_cfg.general.failsafe_dwell_sec {
+        cfg.failsafe_dwell_sec = v;
+    }
+    if let Some(v) = file_cfg.general.fan_stop_dwell_sec {
+        cfg.fan_stop_dwell_sec = v;
+    }
+    if let Some(v) = file_cfg.general.failsafe_hold_sec {
+        cfg.failsafe_hold_sec = v;
+    }
+    if let Some(v) = file_cfg.general.failsafe_ramp_sec {
+        cfg.failsafe_ramp_sec = v;
+    }
+    if let Some(v) = file_cfg.general.failsafe_escalate_sec {
+        cfg.failsafe_escalate_sec = v;
+    }
+    if let Some(v) = file_cfg.general.failsafe_rediscover {
+        cfg.failsafe_rediscover = v;
+    }
+    if let Some(v) = file_cfg.general.failsafe_rebind {
+        cfg.failsafe_rebind = v;
+    }
+    if let Some(v) = file_cfg.general.alert_command {
+        cfg.alert_command = Some(v);
+    }
+    if let Some(v) = file_cfg.general.watchdog {
+        cfg.watchdog = Some(v);
+    }
+    if let Some(v) = file_cfg.general.readback_retries {
+        cfg.readback_retries = v;
+    }
+    if let Some(v) = file_cfg.general.write_retries {
+        cfg.write_retries = v;
+    }
+    if let Some(v) = file_cfg.general.write_retry_delay_ms {
+        cfg.write_retry_delay_ms = v;
+    }
+    if let Some(v) = file_cfg.general.ambient_reference {
+        cfg.ambient_reference = Some(v);
+    }
+    if let Some(v) = file_cfg.general.ambient_sensor {
+        cfg.ambient_sensor = Some(v);
+    }
+    if let Some(v) = file_cfg.general.ambient_max_shift {
+        cfg.ambient_max_shift = v;
+    }
+    if let Some(v) = file_cfg.general.throttle_bias {
+        cfg.throttle_bias = v;
+    }
+    if let Some(v) = file_cfg.general.sync_fans {
+        cfg.sync_fans = v;
+    }
+    if let Some(v) = file_cfg.general.shutdown_c {
+        cfg.shutdown_c = Some(v);
+    }
+    if let Some(v) = file_cfg.general.shutdown_sec {
+        cfg.shutdown_sec = v;
+    }
+    if let Some(v) = file_cfg.general.shutdown_action {
+        cfg.shutdown_action = v;
+    }
+    if let Some(v) = file_cfg.general.shutdown_command {
+        cfg.shutdown_command = Some(v);
+    }
+    if let Some(v) = file_cfg.general.throttle_hold_sec {
+        cfg.throttle_hold_sec = v;
+    }
+    if let Some(v) = file_cfg.general.dust_clean_interval_h {
+        cfg.dust_clean_interval_h = v;
+    }
+    if let Some(v) = file_cfg.general.dust_clean_sec {
+        cfg.dust_clean_sec = v;
+    }
+    if let Some(v) = file_cfg.general.dust_clean_max_temp {
+        cfg.dust_clean_max_temp = v;
+    }
+    if let Some(v) = file_cfg.general.dust_clean_max_load {
+        cfg.dust_clean_max_load = v;
+    }
+    if let Some(v) = file_cfg.general.runtime_dir {
+        cfg.runtime_dir = v;
+    }
+    if let Some(v) = file_cfg.general.history_sec {
+        cfg.history_sec = v;
+    }
+    if let Some(v) = file_cfg.general.state_dir {
+        cfg.state_dir = v;
+    }
+    if let Some(v) = file_cfg.general.stats_thresholds {
+        cfg.stats_thresholds = v;
+    }
+    if let Some(v) = file_cfg.general.stats_keep_days {
+        cfg.stats_keep_days = v;
+    }
+    if let Some(v) = file_cfg.general.sqlite_path {
+        cfg.sqlite_path = Some(v);
+    }
+    if let Some(v) = file_cfg.general.sqlite_keep_days {
+        cfg.sqlite_keep_days = v;
+    }
+    if let Some(v) = file_cfg.general.archive {
+        cfg.archive = v;
+    }
+    if let Some(v) = file_cfg.general.script {
+        cfg.script = Some(v);
+    }
+    if let Some(v) = file_cfg.general.syslog {
+        cfg.syslog = Some(v);
+    }
+    if let Some(v) = file_cfg.general.syslog_facility {
+        cfg.syslog_facility = v;
+    }
 
     if let Some(v) = file_cfg.sensors.cpu_names {
         cfg.cpu_sensor_names = v;
     }
+    if let Some(v) = file_cfg.sensors.battery_names {
+        cfg.battery_sensor_names = v;
+    }
     if let Some(v) = file_cfg.sensors.mem_names {
         cfg.mem_sensor_names = v;
     }
     if let Some(v) = file_cfg.sensors.mem_fallback_to_cpu {
         cfg.mem_fallback_to_cpu = v;
     }
+    for p in file_cfg.sensors.plugins {
+        let zone = p.zone.unwrap_or_else(|| "cpu".to_string());
+        if zone != "cpu" && zone != "mem" {
+            return Err(format!(
+                "sensors.plugins.{}.zone must be \"cpu\" or \"mem\", got \"{zone}\"",
+                p.name
+            )
+            .into());
+        }
+        cfg.sensor_plugins.push(PluginConfig {
+            name: p.name,
+            zone,
+            path: p.path,
+            config: p.config.unwrap_or_default(),
+            allow_paths: p.allow_paths,
+        });
+    }
+    for g in file_cfg.sensors.nvml {
+        let zone = g.zone.unwrap_or_else(|| "cpu".to_string());
+        if zone != "cpu" && zone != "mem" {
+            return Err(format!(
+                "sensors.nvml.{}.zone must be \"cpu\" or \"mem\", got \"{zone}\"",
+                g.name
+            )
+            .into());
+        }
+        cfg.nvml_sensors.push(NvmlConfig {
+            name: g.name,
+            zone,
+            gpu: g.gpu.unwrap_or(nvml_sensor::GpuId::Index(0)),
+        });
+    }
 
     if let Some(v) = file_cfg.curves.cpu {
-        cfg.cpu_curve = v;
+        cfg.cpu_curve = v.parse("curves.cpu")?;
     }
     if let Some(v) = file_cfg.curves.mem {
-        cfg.mem_curve = v;
+        cfg.mem_curve = v.parse("curves.mem")?;
+    }
+    if let Some(v) = file_cfg.curves.battery {
+        cfg.battery_curve = v.parse("curves.battery")?;
+    }
+    if let Some(q) = file_cfg.quiet {
+        cfg.quiet = Some(Quiet {
+            idle_sec: q.idle_sec.unwrap_or(300.0),
+            cpu_curve: q.cpu.map(|c| c.parse("quiet.cpu")).transpose()?,
+            mem_curve: q.mem.map(|c| c.parse("quiet.mem")).transpose()?,
+        });
+    }
+
+    let mut zones = file_cfg.zones;
+    let system_file = zones.remove("system");
+    if let Some(curve) = file_cfg.curves.system {
+        let z = system_file.unwrap_or_default();
+        if z.predict_sec.is_some() || z.predict_samples.is_some() || z.range.is_some() {
+            return Err("zones.system: only combine and weights apply; \
+                        predict_sec, predict_samples and range belong to cpu and mem"
+                .into());
+        }
+        let combine = match z.combine.as_deref() {
+            None | Some("max") => Combine::Max,
+            Some("sum") => Combine::Sum,
+            Some(other) => {
+                return Err(format!(
+                    "zones.system.combine must be \"max\" or \"sum\", got \"{other}\""
+                )
+                .into())
+            }
+        };
+        let weights = z
+            .weights
+            .map(|w| w.into_iter().collect())
+            .unwrap_or_else(|| vec![("cpu".to_string(), 1.0), ("mem".to_string(), 1.0)]);
+        cfg.system = Some(SystemZone {
+            combine,
+            weights,
+            curve: curve.parse("curves.system")?,
+        });
+    } else if system_file.is_some() {
+        return Err("zones.system needs a curves.system to map its score to a duty".into());
+    }
+
+    for (name, z) in zones {
+        let key = format!("zones.{name}");
+        let zone = cfg
+            .zones
+            .iter_mut()
+            .find(|x| x.name == name)
+            .ok_or_else(|| format!("{key}: unknown zone, expected cpu, mem, battery or system"))?;
+        if name == "battery" && (z.predict_sec.is_some() || z.predict_samples.is_some()) {
+            return Err(format!("{key}: only range applies to the battery zone").into());
+        }
+        if z.combine.is_some() || z.weights.is_some() {
+            return Err(format!("{key}: combine and weights only apply to zones.system").into());
+        }
+        if let Some((lo, hi)) = z.range {
+            if lo.is_nan() || hi.is_nan() || lo >= hi {
+                return Err(format!("{key}.range must be [low, high] with low < high").into());
+            }
+            zone.range = Some((lo, hi));
+        }
+        if let Some(v) = z.predict_sec {
+            if v.is_nan() || v < 0.0 {
+                return Err(format!("{key}.predict_sec must be >= 0").into());
+            }
+            zone.predict_sec = v;
+        }
+        if let Some(v) = z.predict_samples {
+            if v < 2 {
+                return Err(format!("{key}.predict_samples must be >= 2").into());
+            }
+            zone.predict_samples = v;
+        }
+    }
+
+    for f in file_cfg.fans {
+        if f.enabled == Some(false) {
+            cfg.fans.retain(|x| x.name != f.name);
+            continue;
+        }
+        let fan = parse_fan(f)?;
+        match cfg.fans.iter_mut().find(|x| x.name == fan.name) {
+            Some(slot) => *slot = fan,
+            None => cfg.fans.push(fan),
+        }
     }
 
+    validate_config(&cfg)?;
     Ok(cfg)
 }
 
+/// `[[fans]]` entries named `fan1`/`fan2` replace the built-in FEVM fans
+/// (or drop them with `enabled = false`); any other name adds a fan.
+fn parse_fan(f: FanFile) -> Result<FanConfig, String> {
+    let key = format!("fans.{}", f.name);
+    let zone = f.zone.unwrap_or_else(|| "cpu".to_string());
+    if zone != "cpu" && zone != "mem" && zone != "system" {
+        return Err(format!(
+            "{key}.zone must be \"cpu\", \"mem\" or \"system\", got \"{zone}\""
+        ));
+    }
+    let backend = match f.backend.as_deref().unwrap_or("sysfs") {
+        "sysfs" => FanBackend::Sysfs {
+            path: f.path.ok_or_else(|| format!("{key}.path is required"))?,
+        },
+        "pwmchip" => FanBackend::PwmChip {
+            chip: f.chip.ok_or_else(|| format!("{key}.chip is required"))?,
+            channel: f.channel.unwrap_or(0),
+            // 25 kHz, the 4-pin fan PWM spec frequency.
+            period_ns: f.period_ns.unwrap_or(40_000),
+            invert: f.invert.unwrap_or(false),
+        },
+        "cros_ec" => FanBackend::CrosEc {
+            channel: f.channel.unwrap_or(1),
+        },
+        "hwmon" => FanBackend::Hwmon {
+            chip: f.chip.ok_or_else(|| format!("{key}.chip is required"))?,
+            channel: f.channel.unwrap_or(1),
+        },
+        "dell_smm" => FanBackend::DellSmm {
+            channel: f.channel.unwrap_or(1),
+            states: f.states.unwrap_or(3).max(2),
+        },
+        "thinkpad" => FanBackend::ThinkPad {
+            path: f.path.unwrap_or_else(|| "/proc/acpi/ibm/fan".to_string()),
+            watchdog_sec: f.watchdog_sec.unwrap_or(30).clamp(1, 120),
+            full_speed: f.full_speed.unwrap_or(false),
+        },
+        "pwm_fan" => FanBackend::PwmFan,
+        "gpio_fan" => FanBackend::GpioFan,
+        "cooling_device" => FanBackend::CoolingDevice {
+            device: f
+                .device
+                .ok_or_else(|| format!("{key}.device is required"))?,
+        },
+        "usb_hid" => {
+            let name = f
+                .device
+                .ok_or_else(|| format!("{key}.device is required"))?;
+            let model = usb_hid::Model::parse(&name).ok_or_else(|| {
+                format!(
+                    "{key}.device must be \"smart_device_v2\" or \"commander_pro\", \
+                     got \"{name}\""
+                )
+            })?;
+            let channel = f.channel.unwrap_or(1);
+            if channel == 0 || channel > model.channels() {
+                return Err(format!("{key}.channel must be 1..={}", model.channels()));
+            }
+            FanBackend::UsbHid {
+                model,
+                channel,
+                serial: f.serial,
+            }
+        }
+        other => return Err(format!("{key}.backend: unknown backend \"{other}\"")),
+    };
+    if let Some(v) = f.reassert_sec {
+        if v.is_nan() || v <= 0.0 {
+            return Err(format!("{key}.reassert_sec must be > 0"));
+        }
+    }
+    let mut fan = FanConfig::new(&f.name, &zone, backend);
+    fan.reassert_sec = f.reassert_sec;
+    if let Some(levels) = f.levels {
+        if levels.is_empty() || levels.windows(2).any(|w| w[1] <= w[0]) {
+            return Err(format!(
+                "{key}.levels must be non-empty and strictly increasing"
+            ));
+        }
+        if levels.iter().any(|l| !(0..=100).contains(l)) {
+            return Err(format!("{key}.levels must be within 0..100"));
+        }
+        fan.levels = levels;
+    }
+    if let Some(v) = f.level_hysteresis {
+        fan.level_hysteresis = v.max(0);
+    }
+    fan.rpm_path = f.rpm_path;
+    if let Some(table) = f.calibration {
+        if table.len() < 2
+            || table
+                .windows(2)
+                .any(|w| w[1].0 <= w[0].0 || w[1].1 < w[0].1)
+        {
+            return Err(format!(
+                "{key}.calibration needs at least two points, with requested duties \
+                 strictly increasing and written duties not decreasing"
+            ));
+        }
+        if table
+            .iter()
+            .any(|(t, d)| !(0.0..=100.0).contains(t) || !(0..=100).contains(d))
+        {
+            return Err(format!("{key}.calibration must be within 0..100"));
+        }
+        fan.calibration = table;
+    }
+    fan.failsafe = match f.failsafe.as_deref().unwrap_or("duty") {
+        "duty" => FailsafeStrategy::Duty,
+        "hold" => FailsafeStrategy::Hold,
+        "ramp" => {
+            let sec = f.failsafe_ramp_up_sec.unwrap_or(60.0);
+            if sec.is_nan() || sec <= 0.0 {
+                return Err(format!("{key}.failsafe_ramp_up_sec must be > 0"));
+            }
+            FailsafeStrategy::Ramp { sec }
+        }
+        "auto" if fan.backend.has_auto() => FailsafeStrategy::Auto,
+        "auto" => {
+            return Err(format!(
+                "{key}.failsafe = \"auto\" needs a backend with firmware automatic control \
+                 (cros_ec, hwmon, dell_smm or thinkpad)"
+            ))
+        }
+        other => {
+            return Err(format!(
+                "{key}.failsafe must be \"duty\", \"hold\", \"ramp\" or \"auto\", got \"{other}\""
+            ))
+        }
+    };
+    Ok(fan)
+}
+
+fn check_curve(curve: &Curve, key: &str) -> Result<(), String> {
+    if curve.is_empty() {
+        return Err(format!("{key} must not be empty"));
+    }
+    for w in curve.windows(2) {
+        if w[1].0 <= w[0].0 {
+            return Err(format!("{key} temperatures must be strictly increasing"));
+        }
+    }
+    Ok(())
+}
+
+fn validate_config(cfg: &Config) -> Result<(), String> {
+    if cfg.poll_sec.is_nan() || cfg.poll_sec <= 0.0 {
+        return Err(format!(
+            "general.poll_sec must be > 0, got {}",
+            cfg.poll_sec
+        ));
+    }
+    if let Some(v) = cfg.sample_sec {
+        if v.is_nan() || v <= 0.0 || v > cfg.poll_sec {
+            return Err(format!(
+                "general.sample_sec must be > 0 and <= poll_sec ({}), got {v}",
+                cfg.poll_sec
+            ));
+        }
+    }
+    if cfg.min_duty > cfg.max_duty {
+        return Err(format!(
+            "general.min_duty ({}) must not exceed general.max_duty ({})",
+            cfg.min_duty, cfg.max_duty
+        ));
+    }
+    for (v, key) in [
+        (cfg.duty_rise_sec, "duty_rise_sec"),
+        (cfg.duty_fall_sec, "duty_fall_sec"),
+        (cfg.failsafe_dwell_sec, "failsafe_dwell_sec"),
+        (cfg.fan_stop_dwell_sec, "fan_stop_dwell_sec"),
+        (cfg.failsafe_hold_sec, "failsafe_hold_sec"),
+        (cfg.failsafe_ramp_sec, "failsafe_ramp_sec"),
+        (cfg.failsafe_escalate_sec, "failsafe_escalate_sec"),
+        (cfg.dust_clean_interval_h, "dust_clean_interval_h"),
+        (cfg.dust_clean_sec, "dust_clean_sec"),
+        (cfg.throttle_hold_sec, "throttle_hold_sec"),
+        (cfg.ambient_max_shift, "ambient_max_shift"),
+        (cfg.shutdown_sec, "shutdown_sec"),
+    ] {
+        if v.is_nan() || v < 0.0 {
+            return Err(format!("general.{key} must be >= 0, got {v}"));
+        }
+    }
+    if let Some(v) = cfg.shutdown_c {
+        if !v.is_finite() || v <= 0.0 {
+            return Err(format!("general.shutdown_c must be > 0, got {v}"));
+        }
+    }
+    match cfg.shutdown_action.as_str() {
+        "poweroff" | "suspend" => {}
+        "command" if cfg.shutdown_command.is_some() => {}
+        "command" => {
+            return Err(
+                "general.shutdown_action = \"command\" needs general.shutdown_command".into(),
+            )
+        }
+        other => {
+            return Err(format!(
+                "general.shutdown_action must be \"poweroff\", \"suspend\" or \"command\", \
+                 got \"{other}\""
+            ))
+        }
+    }
+    let quiet = cfg.quiet.as_ref();
+    for (curve, key) in [
+        (Some(&cfg.cpu_curve), "curves.cpu"),
+        (Some(&cfg.mem_curve), "curves.mem"),
+        (Some(&cfg.battery_curve), "curves.battery"),
+        (quiet.and_then(|q| q.cpu_curve.as_ref()), "quiet.cpu"),
+        (quiet.and_then(|q| q.mem_curve.as_ref()), "quiet.mem"),
+    ] {
+        if let Some(ZoneCurve::Points(points)) = curve {
+            check_curve(points, key)?;
+        }
+    }
+    if let Some(q) = quiet {
+        if q.idle_sec.is_nan() || q.idle_sec < 0.0 {
+            return Err(format!("quiet.idle_sec must be >= 0, got {}", q.idle_sec));
+        }
+    }
+    match &cfg.system {
+        Some(system) => {
+            if let ZoneCurve::Points(points) = &system.curve {
+                check_curve(points, "curves.system")?;
+            }
+            for (zone, w) in &system.weights {
+                if !cfg.zones.iter().any(|z| &z.name == zone) {
+                    return Err(format!(
+                        "zones.system.weights: unknown zone \"{zone}\", \
+                         expected cpu, mem or battery"
+                    ));
+                }
+                if !w.is_finite() || *w < 0.0 {
+                    return Err(format!("zones.system.weights.{zone} must be >= 0, got {w}"));
+                }
+            }
+        }
+        None => {
+            if let Some(f) = cfg.fans.iter().find(|f| f.zone == "system") {
+                return Err(format!(
+                    "fans.{}: zone \"system\" needs a curves.system",
+                    f.name
+                ));
+            }
+        }
+    }
+    if let Some(target) = &cfg.syslog {
+        syslog::check_target(target)?;
+    }
+    syslog::facility_code(&cfg.syslog_facility)?;
+    Ok(())
+}
+
+/// Legal but suspicious settings; reported on startup and by `validate`.
+fn lint_config(cfg: &Config) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    if !(0..=100).contains(&cfg.min_duty) || !(0..=100).contains(&cfg.max_duty) {
+        warnings.push(format!(
+            "min_duty/max_duty ({}/{}) outside 0..100; the driver clamps to 0..100",
+            cfg.min_duty, cfg.max_duty
+        ));
+    }
+    if cfg.min_duty == 0 {
+        warnings.push("min_duty = 0 lets fans stop completely; see docs/SAFETY.md".to_string());
+    }
+    if cfg.failsafe_duty < cfg.min_duty {
+        warnings.push(format!(
+            "failsafe_duty ({}) is below min_duty ({}); failsafe will write {} instead",
+            cfg.failsafe_duty, cfg.min_duty, cfg.min_duty
+        ));
+    }
+    if cfg.failsafe_duty > cfg.max_duty {
+        warnings.push(format!(
+            "failsafe_duty ({}) is above max_duty ({}); failsafe will write {} instead",
+            cfg.failsafe_duty, cfg.max_duty, cfg.max_duty
+        ));
+    }
+    if cfg.ambient_reference.is_some() && cfg.ambient_max_shift > 10.0 {
+        warnings.push(format!(
+            "ambient_max_shift = {} lets a hot room take more than 10C off the curves \
+             before their last segment",
+            cfg.ambient_max_shift
+        ));
+    }
+    if cfg.duty_rise_sec > 10.0 {
+        warnings.push(format!(
+            "duty_rise_sec = {} is above 10s; fans will lag behind a sudden load",
+            cfg.duty_rise_sec
+        ));
+    }
+    if cfg.poll_sec > 10.0 {
+        warnings.push(format!(
+            "poll_sec = {} is above 10s; fans will react slowly to load spikes",
+            cfg.poll_sec
+        ));
+    }
+
+    let system = cfg.system.as_ref().map(|s| ("curves.system", &s.curve));
+    for (key, curve) in [
+        ("curves.cpu", &cfg.cpu_curve),
+        ("curves.mem", &cfg.mem_curve),
+    ]
+    .into_iter()
+    .chain(system)
+    {
+        if let ZoneCurve::Expr(e) = curve {
+            let (lo, hi) = EXPR_LINT_RANGE_C;
+            if let Some(t) = (lo..=hi).find(|t| !e.eval(f64::from(*t)).is_finite()) {
+                warnings.push(format!(
+                    "{key} is not a number at {t}C; that cycle will fall back to failsafe"
+                ));
+            }
+        }
+        let curve = &curve.points();
+        if curve.is_empty() {
+            continue;
+        }
+        let last = curve[curve.len() - 1];
+        if last.1 < cfg.max_duty {
+            warnings.push(format!(
+                "{key} tops out at {}% (at {}C) and never reaches max_duty ({}%)",
+                last.1, last.0, cfg.max_duty
+            ));
+        }
+        if curve[0].1 < cfg.min_duty {
+            warnings.push(format!(
+                "{key} starts at {}%, below min_duty ({}%); those points are clamped",
+                curve[0].1, cfg.min_duty
+            ));
+        }
+        if curve.windows(2).any(|w| w[1].1 < w[0].1) {
+            warnings.push(format!("{key} duty decreases as temperature rises"));
+        }
+        if curve.iter().any(|&(_, d)| !(0..=100).contains(&d)) {
+            warnings.push(format!("{key} has duties outside 0..100"));
+        }
+    }
+
+    for name in &cfg.cpu_sensor_names {
+        if cfg.mem_sensor_names.contains(name) {
+            warnings.push(format!("sensor '{name}' feeds both the cpu and mem zones"));
+        }
+    }
+    for zone in &cfg.zones {
+        let window = cfg.sample_sec.unwrap_or(cfg.poll_sec) * (zone.predict_samples - 1) as f64;
+        if zone.predict_sec > window {
+            warnings.push(format!(
+                "zones.{}.predict_sec ({}s) looks further ahead than the {window}s of samples \
+                 it fits; sensor noise will be amplified",
+                zone.name, zone.predict_sec
+            ));
+        }
+    }
+    for fan in &cfg.fans {
+        if let FanBackend::ThinkPad { watchdog_sec, .. } = fan.backend {
+            let interval = fan.reassert_sec.unwrap_or(cfg.poll_sec).min(cfg.poll_sec);
+            if interval >= watchdog_sec as f64 {
+                warnings.push(format!(
+                    "fan {} is rewritten every {interval}s but its watchdog_sec is {watchdog_sec}; \
+                     the firmware will keep falling back to auto",
+                    fan.name
+                ));
+            }
+        }
+    }
+    for (i, a) in cfg.fans.iter().enumerate() {
+        for b in &cfg.fans[i + 1..] {
+            if a.backend == b.backend {
+                warnings.push(format!(
+                    "fans {} and {} drive the same output",
+                    a.name, b.name
+                ));
+            }
+        }
+    }
+    for fan in &cfg.fans {
+        if !fan.calibration.is_empty() && lerp_curve(cfg.min_duty as f64, &fan.calibration) == 0 {
+            warnings.push(format!(
+                "fan {} calibration writes 0 at min_duty ({}); the fan may stop",
+                fan.name, cfg.min_duty
+            ));
+        }
+    }
+    if cfg.sqlite_path.is_some() && !cfg!(feature = "sqlite") {
+        warnings.push(
+            "general.sqlite_path is set but this build has no sqlite feature; \
+             nothing will be logged"
+                .to_string(),
+        );
+    }
+    if let Some(path) = &cfg.watchdog {
+        let (timeout, nowayout) = watchdog::Watchdog::info(path);
+        if let Some(timeout) = timeout.filter(|t| *t < cfg.poll_sec * 2.0 + 5.0) {
+            warnings.push(format!(
+                "{path} times out after {timeout}s, too close to poll_sec ({}); \
+                 a slow cycle will reboot the machine",
+                cfg.poll_sec
+            ));
+        }
+        if nowayout {
+            warnings.push(format!(
+                "{path} has nowayout set; stopping the daemon will reboot the machine"
+            ));
+        }
+    }
+
+    warnings
+}
+
 fn find_hwmons_by_name(name: &str) -> Vec<String> {
     let mut out = Vec::new();
     if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
@@ -167,12 +1251,29 @@ fn find_hwmons_by_name(name: &str) -> Vec<String> {
     out
 }
 
-fn resolve_hwmons(names: &[String]) -> Vec<String> {
-    let mut out = Vec::new();
+/// A hwmon directory, optionally narrowed to one temperature channel.
+#[derive(Debug, Clone)]
+struct HwmonSensor {
+    dir: String,
+    channel: Option<String>,
+}
+
+/// Sensor names are hwmon chip names, optionally followed by `/tempN` or
+/// `/<label>` to pick a single channel, e.g. `nct6798/CPUTIN`. Super I/O
+/// chips expose many channels and the unconnected ones read garbage.
+fn resolve_hwmons(names: &[String]) -> Vec<HwmonSensor> {
+    let mut out: Vec<HwmonSensor> = Vec::new();
     for name in names {
-        for hw in find_hwmons_by_name(name) {
-            if !out.contains(&hw) {
-                out.push(hw);
+        let (chip, channel) = match name.split_once('/') {
+            Some((chip, channel)) => (chip, Some(channel.to_string())),
+            None => (name.as_str(), None),
+        };
+        for dir in find_hwmons_by_name(chip) {
+            if !out.iter().any(|s| s.dir == dir && s.channel == channel) {
+                out.push(HwmonSensor {
+                    dir,
+                    channel: channel.clone(),
+                });
             }
         }
     }
@@ -185,18 +1286,37 @@ fn read_temp_millic(path: &Path) -> io::Result<f64> {
     Ok(v as f64 / 1000.0)
 }
 
-fn max_temp_in_hwmons(hwmons: &[String]) -> Result<f64, Box<dyn std::error::Error>> {
+fn channel_matches(dir: &Path, prefix: &str, channel: &str) -> bool {
+    if prefix == channel {
+        return true;
+    }
+    fs::read_to_string(dir.join(format!("{prefix}_label")))
+        .map(|label| label.trim() == channel)
+        .unwrap_or(false)
+}
+
+fn max_temp_in_hwmons(hwmons: &[HwmonSensor]) -> Result<f64, Box<dyn std::error::Error>> {
     let mut temps: Vec<f64> = Vec::new();
     for hw in hwmons {
-        for entry in fs::read_dir(hw)? {
+        let dir = Path::new(&hw.dir);
+        for entry in fs::read_dir(dir)? {
             let entry = entry?;
             let name = entry.file_name();
             let name = name.to_string_lossy();
-            if name.starts_with("temp") && name.ends_with("_input") {
-                if let Ok(v) = read_temp_millic(&entry.path()) {
-                    temps.push(v);
+            let Some(prefix) = name.strip_suffix("_input") else {
+                continue;
+            };
+            if !prefix.starts_with("temp") {
+                continue;
+            }
+            if let Some(channel) = &hw.channel {
+                if !channel_matches(dir, prefix, channel) {
+                    continue;
                 }
             }
+            if let Ok(v) = read_temp_millic(&entry.path()) {
+                temps.push(v);
+            }
         }
     }
 
@@ -206,6 +1326,29 @@ fn max_temp_in_hwmons(hwmons: &[String]) -> Result<f64, Box<dyn std::error::Erro
         .ok_or_else(|| "no temp*_input found".into())
 }
 
+/// The hwmon sensors selected for a zone, read together as one source.
+struct HwmonSource(Vec<HwmonSensor>);
+
+impl SensorSource for HwmonSource {
+    fn read(&mut self) -> Result<f64, Box<dyn std::error::Error>> {
+        max_temp_in_hwmons(&self.0)
+    }
+
+    fn describe(&self) -> String {
+        let names: Vec<String> = self
+            .0
+            .iter()
+            .map(|s| match &s.channel {
+                Some(channel) => format!("{}/{channel}", s.dir),
+                None => s.dir.clone(),
+            })
+            .collect();
+        names.join(",")
+    }
+}
+
+type Sources = Vec<Box<dyn SensorSource>>;
+
 fn lerp_curve(temp_c: f64, curve: &Curve) -> i32 {
     if temp_c <= curve[0].0 {
         return curve[0].1;
@@ -226,64 +1369,2026 @@ fn lerp_curve(temp_c: f64, curve: &Curve) -> i32 {
     curve[curve.len() - 1].1
 }
 
-fn clamp_duty(duty: i32, min_duty: i32, max_duty: i32) -> i32 {
-    duty.clamp(min_duty, max_duty)
-}
+/// Temperatures an expression curve is sampled at for `validate`.
+const EXPR_LINT_RANGE_C: (i32, i32) = (20, 100);
 
-fn write_duty(path: &str, duty: i32, min_duty: i32, max_duty: i32) -> io::Result<()> {
-    fs::write(path, clamp_duty(duty, min_duty, max_duty).to_string())
+/// A zone's temperature-to-duty mapping.
+#[derive(Debug, Clone)]
+enum ZoneCurve {
+    /// Points interpolated linearly, flat beyond both ends.
+    Points(Curve),
+    /// An expression of the temperature `t`.
+    Expr(expr::Expr),
 }
 
-fn config_path_from_args() -> String {
-    let args: Vec<String> = env::args().collect();
-    let mut idx = 1usize;
-    while idx < args.len() {
-        if args[idx] == "--config" && idx + 1 < args.len() {
-            return args[idx + 1].clone();
+impl ZoneCurve {
+    fn eval(&self, temp_c: f64) -> Result<i32, String> {
+        match self {
+            Self::Points(curve) => Ok(lerp_curve(temp_c, curve)),
+            Self::Expr(e) => {
+                let duty = e.eval(temp_c);
+                if !duty.is_finite() {
+                    return Err(format!("curve expression gives {duty} at {temp_c:.1}C"));
+                }
+                Ok(duty.clamp(i32::MIN as f64, i32::MAX as f64).round() as i32)
+            }
         }
-        idx += 1;
     }
-    "/etc/fevm-fan-curve.toml".to_string()
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = config_path_from_args();
-    let cfg = load_config(&config_path)?;
 
-    let cpu_hwmons = resolve_hwmons(&cfg.cpu_sensor_names);
-    if cpu_hwmons.is_empty() {
-        return Err(format!("CPU hwmon not found: {:?}", cfg.cpu_sensor_names).into());
+    /// `temp_c` with the ambient `shift` taken off. A hot room (positive
+    /// shift) takes less and less off across the last segment, none at its
+    /// end, so the curve still reaches full cooling where it is meant to
+    /// without a step in the duty; an expression has no segments, so for it
+    /// only a cold room shifts.
+    fn ambient_shifted(&self, temp_c: f64, shift: f64) -> f64 {
+        if shift <= 0.0 {
+            return temp_c - shift;
+        }
+        let (start, end) = match self {
+            Self::Points(curve) if curve.len() >= 2 => {
+                (curve[curve.len() - 2].0, curve[curve.len() - 1].0)
+            }
+            _ => return temp_c,
+        };
+        let left = ((end - temp_c) / (end - start)).clamp(0.0, 1.0);
+        temp_c - shift * left
     }
 
-    let mut mem_hwmons = resolve_hwmons(&cfg.mem_sensor_names);
-    if mem_hwmons.is_empty() {
-        if cfg.mem_fallback_to_cpu {
-            mem_hwmons = cpu_hwmons.clone();
-            eprintln!("mem hwmon not found, fallback to CPU");
-        } else {
-            return Err(format!("MEM hwmon not found: {:?}", cfg.mem_sensor_names).into());
+    /// The curve as points: itself, or the expression sampled every degree
+    /// over `EXPR_LINT_RANGE_C`, leaving out where it is not a number.
+    fn points(&self) -> Curve {
+        match self {
+            Self::Points(curve) => curve.clone(),
+            Self::Expr(_) => {
+                let (lo, hi) = EXPR_LINT_RANGE_C;
+                (lo..=hi)
+                    .filter_map(|t| Some((f64::from(t), self.eval(f64::from(t)).ok()?)))
+                    .collect()
+            }
         }
     }
+}
 
-    eprintln!("cpu_hwmons={:?} mem_hwmons={:?}", cpu_hwmons, mem_hwmons);
+impl SystemZone {
+    /// The thermal pressure score for the zone temperatures in `temps`.
+    /// Each is scored 0 at the low end of its range and 100 at the high end
+    /// (clamped), then weighted and combined.
+    fn score(&self, cfg: &Config, temps: &[(&str, f64)]) -> f64 {
+        let scores = self.weights.iter().filter_map(|(zone, w)| {
+            let (_, t) = temps.iter().find(|(name, _)| name == zone)?;
+            let (lo, hi) = zone_range(cfg, zone)?;
+            Some(w * ((t - lo) / (hi - lo) * 100.0).clamp(0.0, 100.0))
+        });
+        match self.combine {
+            Combine::Max => scores.fold(0.0, f64::max),
+            Combine::Sum => scores.sum(),
+        }
+    }
+}
+
+/// The temperatures a zone scores 0 and 100 at: `zones.<name>.range`, or
+/// the first and last point of its curve.
+fn zone_range(cfg: &Config, zone: &str) -> Option<(f64, f64)> {
+    if let Some(range) = cfg.zones.iter().find(|z| z.name == zone)?.range {
+        return Some(range);
+    }
+    let curve = match zone {
+        "cpu" => &cfg.cpu_curve,
+        "mem" => &cfg.mem_curve,
+        "battery" => &cfg.battery_curve,
+        _ => return None,
+    }
+    .points();
+    let (lo, hi) = (curve.first()?.0, curve.last()?.0);
+    (lo < hi).then_some((lo, hi))
+}
+
+/// Extrapolates a zone temperature `predict_sec` ahead with a least-squares
+/// line through the last `predict_samples` readings. The chassis soaks up
+/// heat for minutes, so reacting to where the temperature is heading beats
+/// waiting for it to get there. Only rising trends are used: a falling
+/// temperature is followed as measured.
+struct Predictor {
+    horizon: f64,
+    capacity: usize,
+    samples: VecDeque<(Instant, f64)>,
+}
 
+impl Predictor {
+    fn new(zone: &ZoneConfig) -> Self {
+        Self {
+            horizon: zone.predict_sec,
+            capacity: zone.predict_samples,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn update(&mut self, now: Instant, temp_c: f64) -> f64 {
+        if self.horizon <= 0.0 {
+            return temp_c;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, temp_c));
+        if self.samples.len() < 2 {
+            return temp_c;
+        }
+
+        let t0 = self.samples[0].0;
+        let n = self.samples.len() as f64;
+        let xs: Vec<f64> = self
+            .samples
+            .iter()
+            .map(|(t, _)| t.duration_since(t0).as_secs_f64())
+            .collect();
+        let mean_x = xs.iter().sum::<f64>() / n;
+        let mean_y = self.samples.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let mut sxy = 0.0;
+        let mut sxx = 0.0;
+        for (x, (_, y)) in xs.iter().zip(&self.samples) {
+            sxy += (x - mean_x) * (y - mean_y);
+            sxx += (x - mean_x) * (x - mean_x);
+        }
+        if sxx <= 0.0 {
+            return temp_c;
+        }
+        let slope = sxy / sxx;
+        temp_c.max(temp_c + slope * self.horizon)
+    }
+}
+
+/// The temperature a cycle evaluates the curve at: the prediction when the
+/// zone predicts, else the mean of the readings in `window`, which it
+/// empties. Without `sample_sec` the window only holds the current reading.
+fn filtered(predictor: &mut Predictor, window: &mut Vec<f64>, now: Instant, temp_c: f64) -> f64 {
+    let predicted = predictor.update(now, temp_c);
+    let mean = window.iter().sum::<f64>() / window.len() as f64;
+    window.clear();
+    if predictor.horizon > 0.0 {
+        predicted
+    } else {
+        mean
+    }
+}
+
+fn clamp_duty(duty: i32, min_duty: i32, max_duty: i32) -> i32 {
+    duty.clamp(min_duty, max_duty)
+}
+
+#[derive(Debug, Serialize)]
+struct FanStatus {
+    name: String,
+    zone: String,
+    output: String,
+    duty: Option<i32>,
+    readback: Option<i32>,
+    readback_mismatches: u64,
+    last_mismatch: Option<String>,
+    write_retries: u64,
+    /// Times the duty was found changed by something else between writes.
+    reverts: u64,
+    /// Set once reverts looked like firmware auto control still running.
+    auto_override: bool,
+    /// Handed to firmware automatic control by `failsafe = "auto"`.
+    firmware_auto: bool,
+    #[serde(skip)]
+    revert_streak: u32,
+    /// The curve duty after `duty_rise_sec`/`duty_fall_sec` smoothing,
+    /// unrounded so slow falls still make progress.
+    #[serde(skip)]
+    smoothed: Option<f64>,
+    /// When the fan last started or stopped, for `fan_stop_dwell_sec`.
+    #[serde(skip)]
+    started_or_stopped: Option<Instant>,
+    /// The duty when the current failsafe began, for `hold` and `ramp`.
+    #[serde(skip)]
+    failsafe_from: Option<i32>,
+}
+
+impl FanStatus {
+    fn new(fan: &FanConfig, output: &dyn FanOutput) -> Self {
+        Self {
+            name: fan.name.clone(),
+            zone: fan.zone.clone(),
+            output: output.describe(),
+            duty: None,
+            readback: None,
+            readback_mismatches: 0,
+            last_mismatch: None,
+            write_retries: 0,
+            reverts: 0,
+            auto_override: false,
+            firmware_auto: false,
+            revert_streak: 0,
+            smoothed: None,
+            started_or_stopped: None,
+            failsafe_from: None,
+        }
+    }
+}
+
+const EIO: i32 = 5;
+const EAGAIN: i32 = 11;
+
+/// Writes the output, retrying the errors the WMI/EC path returns when it
+/// NAKs a call under load. Anything else fails immediately.
+fn write_output(
+    output: &mut dyn FanOutput,
+    fan: &mut FanStatus,
+    duty: i32,
+    cfg: &Config,
+) -> io::Result<()> {
+    let mut attempt = 0;
     loop {
+        match output.write(duty) {
+            Err(e)
+                if matches!(e.raw_os_error(), Some(EIO | EAGAIN))
+                    && attempt < cfg.write_retries =>
+            {
+                attempt += 1;
+                fan.write_retries += 1;
+                log_at!(
+                    Warning,
+                    "{}: write failed: {e}; retry {attempt}/{}",
+                    fan.name,
+                    cfg.write_retries
+                );
+                thread::sleep(Duration::from_millis(cfg.write_retry_delay_ms));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Writes `duty` and, when the output can be read back, checks that it
+/// stuck. Some EC firmware silently drops writes during its own thermal
+/// events, so a mismatch is logged and the write retried; it is not treated
+/// as an error.
+fn write_duty(
+    output: &mut dyn FanOutput,
+    fan: &mut FanStatus,
+    duty: i32,
+    cfg: &Config,
+) -> io::Result<()> {
+    let duty = clamp_duty(duty, cfg.min_duty, cfg.max_duty);
+    fan.duty = Some(duty);
+    for attempt in 1..=cfg.readback_retries + 1 {
+        write_output(output, fan, duty, cfg)?;
+        fan.readback = output.read();
+        match fan.readback {
+            Some(actual) if actual != duty => {
+                let msg = format!(
+                    "wrote {duty}, read back {actual} (attempt {attempt}/{})",
+                    cfg.readback_retries + 1
+                );
+                log_at!(Warning, "{}: {msg}", fan.name);
+                fan.readback_mismatches += 1;
+                fan.last_mismatch = Some(msg);
+            }
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+/// Moves the fan's smoothed duty toward `target` over `dt` seconds, with
+/// the rise or fall time constant depending on the direction, and returns
+/// it rounded. Starts from the last duty written, if any.
+fn smooth_duty(fan: &mut FanStatus, target: i32, dt: f64, cfg: &Config) -> i32 {
+    let target = f64::from(target);
+    let next = match fan.smoothed.or(fan.duty.map(f64::from)) {
+        Some(prev) => smooth_step(prev, target, dt, cfg),
+        None => target,
+    };
+    fan.smoothed = Some(next);
+    next.round() as i32
+}
+
+/// Holds a fan stopped, or at its last running duty, until it has been in
+/// that state for `fan_stop_dwell_sec`, so a temperature hovering around
+/// the curve's zero point cannot start and stop it every few cycles.
+fn stop_dwell(fan: &mut FanStatus, duty: i32, cfg: &Config) -> i32 {
+    let Some(prev) = fan.duty else {
+        return duty;
+    };
+    let stopping = clamp_duty(duty, cfg.min_duty, cfg.max_duty) <= 0;
+    if (prev <= 0) == stopping {
+        return duty;
+    }
+    if fan
+        .started_or_stopped
+        .is_some_and(|t| t.elapsed().as_secs_f64() < cfg.fan_stop_dwell_sec)
+    {
+        return prev;
+    }
+    fan.started_or_stopped = Some(Instant::now());
+    duty
+}
+
+/// One step of `smooth_duty`'s exponential approach from `prev`.
+fn smooth_step(prev: f64, target: f64, dt: f64, cfg: &Config) -> f64 {
+    let tau = if target > prev {
+        cfg.duty_rise_sec
+    } else {
+        cfg.duty_fall_sec
+    };
+    if tau > 0.0 {
+        prev + (target - prev) * (1.0 - (-dt / tau).exp())
+    } else {
+        target
+    }
+}
+
+/// The lowest duty allowed while recovering from a failsafe that ended
+/// `since` ago: `failsafe_duty` for `failsafe_hold_sec`, then falling
+/// linearly to `min_duty` over `failsafe_ramp_sec`. None once recovered.
+/// Sensors that fail every few cycles would otherwise flap the fans between
+/// failsafe and the curve.
+fn recovery_floor(cfg: &Config, since: Duration) -> Option<i32> {
+    let t = since.as_secs_f64() - cfg.failsafe_hold_sec;
+    if t <= 0.0 {
+        return Some(cfg.failsafe_duty);
+    }
+    if t >= cfg.failsafe_ramp_sec {
+        return None;
+    }
+    let drop = f64::from(cfg.failsafe_duty - cfg.min_duty) * t / cfg.failsafe_ramp_sec;
+    Some((f64::from(cfg.failsafe_duty) - drop).round() as i32)
+}
+
+/// The duty `fan`'s failsafe strategy asks for now. `hold` and `ramp` are
+/// raised to the escalated `failsafe_duty` once escalation starts.
+fn failsafe_duty(
+    fan_cfg: &FanConfig,
+    fan: &FanStatus,
+    escalation: &escalate::Escalation,
+    cfg: &Config,
+) -> i32 {
+    let fixed = escalation.duty(cfg);
+    let from = fan.failsafe_from.unwrap_or(cfg.failsafe_duty);
+    let duty = match fan_cfg.failsafe {
+        FailsafeStrategy::Duty | FailsafeStrategy::Auto => return fixed,
+        FailsafeStrategy::Hold => from,
+        FailsafeStrategy::Ramp { sec } => {
+            let done = (escalation.failing_sec() / sec).min(1.0);
+            from + (f64::from(cfg.max_duty - from).max(0.0) * done).round() as i32
+        }
+    };
+    if escalation.level() > 0 {
+        duty.max(fixed)
+    } else {
+        duty
+    }
+}
+
+/// The mode reported while in failsafe: being over `shutdown_c` is the
+/// more urgent of the two.
+fn failsafe_mode(critical: &critical::Critical) -> String {
+    if critical.active() {
+        "critical"
+    } else {
+        "failsafe"
+    }
+    .to_string()
+}
+
+/// Applies `fan`'s failsafe strategy for one failed cycle. `auto` falls
+/// back to `failsafe_duty` when the output cannot be handed over, which is
+/// only reported when the failsafe begins.
+fn apply_failsafe(
+    output: &mut dyn FanOutput,
+    fan: &mut FanStatus,
+    fan_cfg: &FanConfig,
+    escalation: &escalate::Escalation,
+    cfg: &Config,
+    entering: bool,
+) {
+    if fan_cfg.failsafe == FailsafeStrategy::Auto && !fan.firmware_auto {
+        match output.set_auto() {
+            Ok(true) => {
+                log_at!(Info, "{}: handed to firmware automatic control", fan.name);
+                fan.firmware_auto = true;
+                fan.duty = None;
+                fan.readback = None;
+            }
+            Ok(false) if entering => log_at!(
+                Warning,
+                "{}: {} has no automatic mode; using failsafe_duty",
+                fan.name,
+                fan.output
+            ),
+            Err(e) if entering => log_at!(
+                Warning,
+                "{}: cannot switch to automatic control: {e}; using failsafe_duty",
+                fan.name
+            ),
+            _ => {}
+        }
+    }
+    if fan.firmware_auto {
+        return;
+    }
+    let duty = failsafe_duty(fan_cfg, fan, escalation, cfg);
+    let _ = write_duty(output, fan, duty, cfg);
+}
+
+/// Reverts in a row before firmware auto control is assumed.
+const REVERT_LIMIT: u32 = 3;
+
+/// Checks that the duty last read back after our write is still in place.
+/// Firmware whose own fan control was never turned off puts its setting
+/// back shortly after every write; once that happens `REVERT_LIMIT` times in
+/// a row, it is reported and the output switched back to manual mode if it
+/// has such a knob.
+fn check_reverted(output: &mut dyn FanOutput, fan: &mut FanStatus) {
+    let (Some(expected), Some(actual)) = (fan.readback, output.read()) else {
+        return;
+    };
+    if actual == expected {
+        fan.revert_streak = 0;
+        return;
+    }
+    log_at!(
+        Warning,
+        "{}: duty changed to {actual} behind our back",
+        fan.name
+    );
+    fan.reverts += 1;
+    fan.revert_streak += 1;
+    if fan.revert_streak != REVERT_LIMIT {
+        return;
+    }
+    fan.auto_override = true;
+    match output.force_manual() {
+        Ok(true) => log_at!(
+            Warning,
+            "{}: firmware auto control keeps overriding our writes; switched {} back to manual",
+            fan.name,
+            fan.output
+        ),
+        Ok(false) => log_at!(
+            Warning,
+            "{}: firmware auto control keeps overriding our writes and {} has no mode to \
+             switch; disable automatic (smart) fan control in the BIOS setup",
+            fan.name,
+            fan.output
+        ),
+        Err(e) => log_at!(
+            Error,
+            "{}: firmware auto control keeps overriding our writes; cannot switch {} to \
+             manual: {e}",
+            fan.name,
+            fan.output
+        ),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    pid: u32,
+    updated_unix: u64,
+    mode: String,
+    /// "normal", or "quiet" while the idle profile is in use.
+    profile: String,
+    last_error: Option<String>,
+    cpu_temp_c: Option<f64>,
+    mem_temp_c: Option<f64>,
+    battery_temp_c: Option<f64>,
+    ambient_c: Option<f64>,
+    /// Degrees subtracted from cpu and mem before their curves.
+    ambient_shift_c: f64,
+    cpu_predicted_c: Option<f64>,
+    mem_predicted_c: Option<f64>,
+    /// The system zone's thermal pressure score, 0..100 per weighted zone.
+    system_score: Option<f64>,
+    /// Whether `throttle_bias` is being applied.
+    throttling: bool,
+    fans: Vec<FanStatus>,
+}
+
+/// Sleeps until `deadline`, rewriting the last duty of fans with
+/// `reassert_sec` in between. Some firmware (dell_smm BIOSes in particular)
+/// quietly puts its own fan setting back a few seconds after every write.
+/// With `sample_sec` set, `sample` is also called at that interval.
+fn sleep_until(
+    deadline: Instant,
+    outputs: &mut [Box<dyn FanOutput>],
+    fans: &mut [FanStatus],
+    cfg: &Config,
+    term: &AtomicBool,
+    sample: &mut dyn FnMut(),
+) {
+    let start = Instant::now();
+    let sample_every = cfg.sample_sec.map(Duration::from_secs_f64);
+    let mut next_sample = sample_every.map(|i| start + i);
+    let intervals: Vec<Option<Duration>> = cfg
+        .fans
+        .iter()
+        .map(|f| f.reassert_sec.map(Duration::from_secs_f64))
+        .collect();
+    let mut next: Vec<Option<Instant>> = intervals.iter().map(|i| i.map(|i| start + i)).collect();
+
+    loop {
+        let now = Instant::now();
+        if now >= deadline || term.load(Ordering::Relaxed) {
+            return;
+        }
+        let wake = next
+            .iter()
+            .chain(&[next_sample])
+            .flatten()
+            .copied()
+            .fold(deadline, Instant::min);
+        thread::sleep(wake.saturating_duration_since(now));
+
+        let now = Instant::now();
+        // The cycle itself reads the sensors at the deadline.
+        if let (Some(due), Some(interval)) = (next_sample, sample_every) {
+            if due <= now && now < deadline {
+                next_sample = Some(due + interval);
+                sample();
+            }
+        }
+        for (i, (output, fan)) in outputs.iter_mut().zip(fans.iter_mut()).enumerate() {
+            let (Some(due), Some(interval)) = (next[i], intervals[i]) else {
+                continue;
+            };
+            if due > now {
+                continue;
+            }
+            next[i] = Some(due + interval);
+            if let Some(duty) = fan.duty {
+                check_reverted(output.as_mut(), fan);
+                if let Err(e) = write_duty(output.as_mut(), fan, duty, cfg) {
+                    log_at!(Warning, "{}: re-assert failed: {e}", fan.name);
+                }
+            }
+        }
+    }
+}
+
+fn status_path(cfg: &Config) -> String {
+    format!("{}/status.toml", cfg.runtime_dir)
+}
+
+fn write_status(cfg: &Config, status: &Status) -> Result<(), Box<dyn std::error::Error>> {
+    let path = status_path(cfg);
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, toml::to_string(status)?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+fn socket_path(cfg: &Config) -> String {
+    format!("{}/control.sock", cfg.runtime_dir)
+}
+
+fn lock_path(cfg: &Config) -> String {
+    format!("{}/lock", cfg.runtime_dir)
+}
+
+/// Takes the instance lock in the runtime directory, so a second daemon (or
+/// a selftest) for the same instance refuses to start instead of fighting
+/// over the fans. Held until the returned file is dropped; `None` (after a
+/// warning) if the lock file cannot be used at all.
+fn lock_instance(cfg: &Config) -> Result<Option<File>, String> {
+    let path = lock_path(cfg);
+    let mut file = match fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(&path)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            log_at!(
+                Warning,
+                "cannot open {path}: {e}; running without the instance lock"
+            );
+            return Ok(None);
+        }
+    };
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let pid = fs::read_to_string(&path).unwrap_or_default();
+            return Err(format!(
+                "{path} is held by pid {}; is this instance already running?",
+                pid.trim()
+            ));
+        }
+        Err(TryLockError::Error(e)) => {
+            log_at!(
+                Warning,
+                "cannot lock {path}: {e}; running without the instance lock"
+            );
+            return Ok(None);
+        }
+    }
+    if let Err(e) = file
+        .set_len(0)
+        .and_then(|()| writeln!(file, "{}", std::process::id()))
+    {
+        log_at!(Warning, "cannot write {path}: {e}");
+    }
+    Ok(Some(file))
+}
+
+fn stats_path(cfg: &Config) -> String {
+    format!("{}/stats.toml", cfg.state_dir)
+}
+
+fn archive_path(cfg: &Config) -> String {
+    format!("{}/archive.rrd", cfg.state_dir)
+}
+
+fn resume_path(cfg: &Config) -> String {
+    format!("{}/last-state.toml", cfg.state_dir)
+}
+
+fn save_resume(cfg: &Config, status: &Status) -> Result<(), Box<dyn std::error::Error>> {
+    resume::Saved {
+        saved_unix: unix_now(),
+        mode: status.mode.clone(),
+        profile: status.profile.clone(),
+        duties: status
+            .fans
+            .iter()
+            .filter_map(|f| Some((f.name.clone(), f.duty?)))
+            .collect(),
+    }
+    .save(&resume_path(cfg))
+}
+
+/// How often accumulated statistics are written to the state directory.
+const STATS_FLUSH_SEC: f64 = 300.0;
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run,
+    Validate,
+    Status,
+    Stats,
+    Export,
+    Selftest,
+    Detect,
+    Read,
+    CurveExport,
+    CurveReplay,
+    Healthcheck,
+    Bench,
+}
+
+#[derive(Debug)]
+struct Cli {
+    command: Command,
+    config_path: String,
+    instance: Option<String>,
+    history: bool,
+    all: bool,
+    days: usize,
+    resolution: usize,
+    force: bool,
+    format: String,
+    output: Option<String>,
+    trace: Option<String>,
+    zone: String,
+    samples: usize,
+    simulate: simulate::Simulate,
+}
+
+fn parse_args() -> Result<Cli, String> {
+    let args: Vec<String> = env::args().collect();
+    let mut cli = Cli {
+        command: Command::Run,
+        config_path: String::new(),
+        instance: None,
+        history: false,
+        all: false,
+        days: 7,
+        resolution: 1,
+        force: false,
+        format: "svg".to_string(),
+        output: None,
+        trace: None,
+        zone: "cpu".to_string(),
+        samples: 50,
+        simulate: simulate::Simulate::default(),
+    };
+    let mut idx = 1usize;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--config" if idx + 1 < args.len() => {
+                cli.config_path = args[idx + 1].clone();
+                idx += 1;
+            }
+            "--instance" if idx + 1 < args.len() => {
+                let name = &args[idx + 1];
+                if name.is_empty()
+                    || name.starts_with('.')
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+                {
+                    return Err(format!("invalid --instance: {name}"));
+                }
+                cli.instance = Some(name.clone());
+                idx += 1;
+            }
+            "--history" => cli.history = true,
+            "--all" => cli.all = true,
+            "--force" => cli.force = true,
+            "--format" if idx + 1 < args.len() => {
+                cli.format = args[idx + 1].clone();
+                idx += 1;
+            }
+            "-o" | "--output" if idx + 1 < args.len() => {
+                cli.output = Some(args[idx + 1].clone());
+                idx += 1;
+            }
+            "--trace" if idx + 1 < args.len() => {
+                cli.trace = Some(args[idx + 1].clone());
+                idx += 1;
+            }
+            "--zone" if idx + 1 < args.len() => {
+                cli.zone = args[idx + 1].clone();
+                idx += 1;
+            }
+            "--simulate-temp" if idx + 1 < args.len() => {
+                cli.simulate.add_temp(&args[idx + 1])?;
+                idx += 1;
+            }
+            "--simulate-sensor-failure" if idx + 1 < args.len() => {
+                cli.simulate.add_sensor_failure(&args[idx + 1])?;
+                idx += 1;
+            }
+            "--simulate-write-error" if idx + 1 < args.len() => {
+                cli.simulate.add_write_error(&args[idx + 1]);
+                idx += 1;
+            }
+            "--resolution" if idx + 1 < args.len() => {
+                cli.resolution = match args[idx + 1].as_str() {
+                    "1s" => 0,
+                    "1m" => 1,
+                    "10m" => 2,
+                    other => return Err(format!("invalid --resolution: {other} (1s, 1m, 10m)")),
+                };
+                idx += 1;
+            }
+            "--samples" if idx + 1 < args.len() => {
+                cli.samples = args[idx + 1]
+                    .parse()
+                    .map_err(|_| format!("invalid --samples: {}", args[idx + 1]))?;
+                idx += 1;
+            }
+            "--days" if idx + 1 < args.len() => {
+                cli.days = args[idx + 1]
+                    .parse()
+                    .map_err(|_| format!("invalid --days: {}", args[idx + 1]))?;
+                idx += 1;
+            }
+            "run" => cli.command = Command::Run,
+            "validate" => cli.command = Command::Validate,
+            "status" => cli.command = Command::Status,
+            "stats" => cli.command = Command::Stats,
+            "export" => cli.command = Command::Export,
+            "selftest" => cli.command = Command::Selftest,
+            "detect" => cli.command = Command::Detect,
+            "read" => cli.command = Command::Read,
+            "healthcheck" => cli.command = Command::Healthcheck,
+            "bench" => cli.command = Command::Bench,
+            "curve" => {
+                cli.command =
+                    match args.get(idx + 1).map(String::as_str) {
+                        Some("export") => Command::CurveExport,
+                        Some("replay") => Command::CurveReplay,
+                        _ => return Err(
+                            "usage: curve export [--format svg] [-o FILE] [--trace FILE]\n       \
+                                    curve replay --trace FILE [--zone cpu|mem|system]"
+                                .to_string(),
+                        ),
+                    };
+                idx += 1;
+            }
+            other => return Err(format!("unknown argument: {other}")),
+        }
+        idx += 1;
+    }
+    if cli.config_path.is_empty() {
+        cli.config_path = match &cli.instance {
+            Some(name) => format!("/etc/fevm-fan-curve@{name}.toml"),
+            None => "/etc/fevm-fan-curve.toml".to_string(),
+        };
+    }
+    Ok(cli)
+}
+
+fn fan_names(cfg: &Config) -> Vec<String> {
+    cfg.fans.iter().map(|f| f.name.clone()).collect()
+}
+
+fn validate(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+    if let Some(path) = &cfg.script {
+        script::Script::load(path, fan_names(&cfg))?;
+    }
+    let warnings = lint_config(&cfg);
+    for w in &warnings {
+        println!("warning: {w}");
+    }
+    println!("{}: OK ({} warning(s))", cli.config_path, warnings.len());
+    Ok(())
+}
+
+fn print_status(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+    if cli.history {
+        let path = socket_path(&cfg);
+        let csv = api::query(&path, "history")
+            .map_err(|e| format!("cannot query {path}: {e}; is the daemon running?"))?;
+        print!("{csv}");
+        return Ok(());
+    }
+    let path = status_path(&cfg);
+    let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read {path}: {e}; is the daemon running?"))?;
+    print!("{raw}");
+    Ok(())
+}
+
+/// Prints one line and exits with a Nagios plugin code: 0 OK, 2 CRITICAL,
+/// 3 UNKNOWN when the config cannot be loaded.
+fn healthcheck(cli: &Cli) -> ! {
+    let cfg = match load_config(&cli.config_path, cli.instance.as_deref()) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("UNKNOWN: {e}");
+            std::process::exit(3);
+        }
+    };
+    match health::check(&cfg) {
+        Ok(summary) => {
+            println!("OK: {summary}");
+            std::process::exit(0);
+        }
+        Err(reason) => {
+            println!("CRITICAL: {reason}");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn print_stats(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+    let path = stats_path(&cfg);
+    let stats = Stats::load(&path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    stats::print(&stats, cli.days);
+    Ok(())
+}
+
+fn export(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+    let path = archive_path(&cfg);
+    let (zones, fans, samples) =
+        archive::read(&path, cli.resolution).map_err(|e| format!("cannot read {path}: {e}"))?;
+    let mut out = History::new(zones, fans, samples.len());
+    for sample in samples {
+        out.push(sample);
+    }
+    print!("{}", out.to_csv());
+    Ok(())
+}
+
+fn export_curves(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    if cli.format != "svg" {
+        return Err(format!("unsupported --format {} (svg)", cli.format).into());
+    }
+    let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+    let trace = match &cli.trace {
+        Some(path) => {
+            let csv = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+            Some(History::from_csv(&csv).map_err(|e| format!("{path}: {e}"))?)
+        }
+        None => None,
+    };
+    let svg = plot::render_svg(&cfg, trace.as_ref());
+    match &cli.output {
+        Some(path) => fs::write(path, svg).map_err(|e| format!("{path}: {e}"))?,
+        None => print!("{svg}"),
+    }
+    Ok(())
+}
+
+/// Runs the config's curve for `--zone` over a recorded `--trace`.
+fn replay_curve(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let path = cli
+        .trace
+        .as_ref()
+        .ok_or("curve replay needs --trace FILE")?;
+    let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+    let csv = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let trace = History::from_csv(&csv).map_err(|e| format!("{path}: {e}"))?;
+    print!("{}", replay::report(&cfg, &trace, &cli.zone)?);
+    Ok(())
+}
+
+/// Loads the plugins and NVIDIA GPUs configured for `zone`.
+fn load_plugins(cfg: &Config, zone: &str) -> Result<Sources, Box<dyn std::error::Error>> {
+    let mut out: Sources = Vec::new();
+    for p in cfg.sensor_plugins.iter().filter(|p| p.zone == zone) {
+        let plugin =
+            wasm_sensor::WasmSensor::load(&p.name, &p.path, &p.config, p.allow_paths.clone())
+                .map_err(|e| format!("sensor plugin {}: {e}", p.name))?;
+        out.push(Box::new(plugin));
+    }
+    for g in cfg.nvml_sensors.iter().filter(|g| g.zone == zone) {
+        let gpu = nvml_sensor::NvmlSensor::open(&g.name, &g.gpu)
+            .map_err(|e| format!("nvml sensor {}: {e}", g.name))?;
+        out.push(Box::new(gpu));
+    }
+    Ok(out)
+}
+
+/// Resolves the cpu and mem sensors, hwmon selectors plus plugins, applying
+/// `mem_fallback_to_cpu`.
+fn resolve_sensors(cfg: &Config) -> Result<(Sources, Sources), Box<dyn std::error::Error>> {
+    let cpu_hwmons = resolve_hwmons(&cfg.cpu_sensor_names);
+    let cpu_plugins = load_plugins(cfg, "cpu")?;
+    if cpu_hwmons.is_empty() && cpu_plugins.is_empty() {
+        return Err(format!("CPU hwmon not found: {:?}", cfg.cpu_sensor_names).into());
+    }
+
+    let mut mem_hwmons = resolve_hwmons(&cfg.mem_sensor_names);
+    let mut mem_plugins = load_plugins(cfg, "mem")?;
+    if mem_hwmons.is_empty() && mem_plugins.is_empty() {
+        if cfg.mem_fallback_to_cpu {
+            mem_hwmons = cpu_hwmons.clone();
+            // Plugins hold state, so the mem zone gets its own instances.
+            mem_plugins = load_plugins(cfg, "cpu")?;
+            log_at!(Info, "mem hwmon not found, fallback to CPU");
+        } else {
+            return Err(format!("MEM hwmon not found: {:?}", cfg.mem_sensor_names).into());
+        }
+    }
+
+    let zone = |hwmons: Vec<HwmonSensor>, plugins: Sources| {
+        let mut out: Sources = Vec::new();
+        if !hwmons.is_empty() {
+            out.push(Box::new(HwmonSource(hwmons)));
+        }
+        out.extend(plugins);
+        out
+    };
+    let cpu = zone(cpu_hwmons, cpu_plugins);
+    let mem = zone(mem_hwmons, mem_plugins);
+    let names =
+        |sources: &Sources| -> Vec<String> { sources.iter().map(|s| s.describe()).collect() };
+    log_at!(
+        Info,
+        "cpu_sensors={:?} mem_sensors={:?}",
+        names(&cpu),
+        names(&mem)
+    );
+    Ok((cpu, mem))
+}
+
+/// Opens the `battery_names` power supplies. Unlike the cpu and mem zones a
+/// missing one is not fatal: most of these boxes have no battery, and a
+/// laptop may have it removed.
+fn resolve_battery(cfg: &Config) -> Sources {
+    let mut out: Sources = Vec::new();
+    for name in &cfg.battery_sensor_names {
+        match power_supply::PowerSupplySource::open(name) {
+            Ok(source) => out.push(Box::new(source)),
+            Err(e) => log_at!(Warning, "{e}; not used for the battery curve"),
+        }
+    }
+    out
+}
+
+/// Opens and initializes every configured fan, in `cfg.fans` order.
+fn open_outputs(cfg: &Config) -> Result<Vec<Box<dyn FanOutput>>, Box<dyn std::error::Error>> {
+    let mut outputs = Vec::new();
+    for fan in &cfg.fans {
+        let mut output = fan::open_output(&fan.backend)
+            .map_err(|e| format!("{}: cannot open output: {e}", fan.name))?;
+        if !fan.levels.is_empty() {
+            output = Box::new(fan::LevelMapped::new(
+                output,
+                fan.levels.clone(),
+                fan.level_hysteresis,
+            ));
+        }
+        if !fan.calibration.is_empty() {
+            output = Box::new(fan::Calibrated::new(output, fan.calibration.clone()));
+        }
+        output
+            .init()
+            .map_err(|e| format!("{}: cannot set up {}: {e}", fan.name, output.describe()))?;
+        log_at!(
+            Info,
+            "{}: zone={} output={}",
+            fan.name,
+            fan.zone,
+            output.describe()
+        );
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = parse_args()?;
+    match cli.command {
+        Command::Validate => return validate(&cli),
+        Command::Status => return print_status(&cli),
+        Command::Stats => return print_stats(&cli),
+        Command::Export => return export(&cli),
+        Command::Selftest => {
+            let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+            return selftest::run(&cfg, cli.force);
+        }
+        Command::CurveExport => return export_curves(&cli),
+        Command::CurveReplay => return replay_curve(&cli),
+        Command::Healthcheck => healthcheck(&cli),
+        Command::Bench => {
+            let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+            return bench::run(&cfg, cli.samples, cli.force);
+        }
+        Command::Read => {
+            let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+            detect::print_readout(&cfg, cli.all);
+            return Ok(());
+        }
+        Command::Detect => {
+            print!("{}", detect::propose());
+            return Ok(());
+        }
+        Command::Run => {}
+    }
+
+    let cfg = load_config(&cli.config_path, cli.instance.as_deref())?;
+    if let Some(target) = &cfg.syslog {
+        let app = match &cli.instance {
+            Some(name) => format!("fevm-fan-curve@{name}"),
+            None => "fevm-fan-curve".to_string(),
+        };
+        if let Err(e) = syslog::init(target, &cfg.syslog_facility, &app) {
+            log_at!(Warning, "{e}; logging to stderr only");
+        }
+    }
+    for w in lint_config(&cfg) {
+        log_at!(Warning, "config warning: {w}");
+    }
+
+    let simulate = &cli.simulate;
+    simulate.check_fans(&fan_names(&cfg))?;
+    if let Some(what) = simulate.describe() {
+        log_at!(Warning, "simulating {what}; for testing only");
+    }
+
+    conflict::check(cli.force)?;
+    let (problems, hint) = access::check(&cfg);
+    if !problems.is_empty() {
+        for p in &problems {
+            log_at!(Error, "{p}");
+        }
+        if let Some(hint) = hint {
+            log_at!(Info, "{hint}");
+        }
+        return Err(format!("{} sensor or fan node(s) not accessible", problems.len()).into());
+    }
+    let (mut cpu_sensors, mut mem_sensors) = resolve_sensors(&cfg)?;
+    let mut battery_sensors = resolve_battery(&cfg);
+    // Before the outputs are opened: a script that fails to load must not
+    // leave the fans in manual mode.
+    let script = match &cfg.script {
+        Some(path) => Some(script::Script::load(path, fan_names(&cfg))?),
+        None => None,
+    };
+
+    if let Err(e) = fs::create_dir_all(&cfg.runtime_dir) {
+        log_at!(
+            Warning,
+            "cannot create {}: {e}; status will not be published",
+            cfg.runtime_dir
+        );
+    }
+    let _lock = lock_instance(&cfg)?;
+
+    let term = Arc::new(AtomicBool::new(false));
+    for sig in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(sig, Arc::clone(&term))?;
+    }
+
+    let mut outputs = simulate.wrap_outputs(&fan_names(&cfg), open_outputs(&cfg)?);
+
+    let mut status = Status {
+        pid: std::process::id(),
+        updated_unix: 0,
+        mode: "normal".to_string(),
+        profile: "normal".to_string(),
+        last_error: None,
+        cpu_temp_c: None,
+        mem_temp_c: None,
+        battery_temp_c: None,
+        ambient_c: None,
+        ambient_shift_c: 0.0,
+        cpu_predicted_c: None,
+        mem_predicted_c: None,
+        system_score: None,
+        throttling: false,
+        fans: cfg
+            .fans
+            .iter()
+            .zip(&outputs)
+            .map(|(fan, output)| FanStatus::new(fan, output.as_ref()))
+            .collect(),
+    };
+    let mut status_error_logged = false;
+
+    // Put the duties of the previous run back before the first sensor read,
+    // so a restart does not audibly reset the fans, and smoothing carries on
+    // from where it was.
+    match resume::Saved::load(&resume_path(&cfg), unix_now()) {
+        Ok(Some(saved)) => {
+            for (output, fan) in outputs.iter_mut().zip(&mut status.fans) {
+                let Some(&duty) = saved.duties.get(&fan.name) else {
+                    continue;
+                };
+                match write_duty(output.as_mut(), fan, duty, &cfg) {
+                    Ok(()) => fan.smoothed = Some(f64::from(duty)),
+                    Err(e) => log_at!(Warning, "{}: cannot restore duty {duty}: {e}", fan.name),
+                }
+            }
+            if saved.profile == "quiet" && cfg.quiet.is_some() {
+                status.profile = saved.profile;
+            }
+            log_at!(
+                Info,
+                "restored duties from {}s ago ({}, {} profile)",
+                unix_now().saturating_sub(saved.saved_unix),
+                saved.mode,
+                status.profile
+            );
+        }
+        Ok(None) => {}
+        Err(e) => log_at!(
+            Warning,
+            "cannot read {}: {e}; starting fresh",
+            resume_path(&cfg)
+        ),
+    }
+
+    let history = Arc::new(Mutex::new(History::new(
+        vec!["cpu".to_string(), "mem".to_string()],
+        cfg.fans.iter().map(|f| f.name.clone()).collect(),
+        (cfg.history_sec / cfg.poll_sec).ceil() as usize,
+    )));
+    if let Err(e) = api::serve(&socket_path(&cfg), Arc::clone(&history)) {
+        log_at!(
+            Warning,
+            "cannot open {}: {e}; history is not available",
+            socket_path(&cfg)
+        );
+    }
+    if let Err(e) = fs::create_dir_all(&cfg.state_dir) {
+        log_at!(
+            Warning,
+            "cannot create {}: {e}; statistics will not be kept",
+            cfg.state_dir
+        );
+    }
+    let mut stats = Stats::load(&stats_path(&cfg)).unwrap_or_else(|e| {
+        log_at!(
+            Warning,
+            "cannot read {}: {e}; starting fresh",
+            stats_path(&cfg)
+        );
+        Stats::default()
+    });
+    let stats_zones = vec!["cpu".to_string(), "mem".to_string()];
+    let stats_fans: Vec<String> = cfg.fans.iter().map(|f| f.name.clone()).collect();
+    let mut sqlite = cfg.sqlite_path.as_ref().and_then(|path| {
+        SqliteLog::open(
+            path,
+            stats_zones.clone(),
+            stats_fans.clone(),
+            cfg.sqlite_keep_days,
+        )
+        .map_err(|e| log_at!(Warning, "cannot open {path}: {e}; sqlite logging disabled"))
+        .ok()
+    });
+    let mut sqlite_error_logged = false;
+    if let Some(db) = &mut sqlite {
+        let _ = db.event(unix_now() as f64, "start", None);
+    }
+    let mut archive = if cfg.archive {
+        Archive::open(&archive_path(&cfg), stats_zones.clone(), stats_fans.clone())
+            .map_err(|e| {
+                log_at!(
+                    Warning,
+                    "cannot open {}: {e}; archive disabled",
+                    archive_path(&cfg)
+                )
+            })
+            .ok()
+    } else {
+        None
+    };
+    let mut archive_error_logged = false;
+    let mut last_cycle = Instant::now();
+    let mut last_flush = Instant::now();
+    let mut stats_error_logged = false;
+
+    let mut cpu_predictor = Predictor::new(&cfg.zones[0]);
+    let mut mem_predictor = Predictor::new(&cfg.zones[1]);
+    // Readings taken by `sample_sec` since the last fan update.
+    let mut cpu_window: Vec<f64> = Vec::new();
+    let mut mem_window: Vec<f64> = Vec::new();
+
+    // Opened last, so a failing startup never arms it.
+    let mut watchdog = cfg.watchdog.as_ref().and_then(|path| {
+        watchdog::Watchdog::open(path)
+            .map_err(|e| log_at!(Error, "cannot open {path}: {e}; running without watchdog"))
+            .ok()
+    });
+    let mut watchdog_error_logged = false;
+    let mut last_failsafe: Option<Instant> = None;
+    let mut escalation = escalate::Escalation::default();
+    let mut dust = dust::DustClean::load(&cfg);
+    let mut critical = critical::Critical::default();
+    let mut throttle = (cfg.throttle_bias != 0).then(throttle::Throttle::open);
+    if throttle.as_ref().is_some_and(throttle::Throttle::is_empty) {
+        log_at!(
+            Warning,
+            "throttle_bias is set but no throttle counters or cpufreq policies were found"
+        );
+        throttle = None;
+    }
+    let mut last_throttle: Option<Instant> = None;
+    let mut ambient = ambient::Ambient::new();
+    let mut ambient_error_logged = false;
+    let mut idle = idle::Idle::new(status.profile == "quiet");
+    let mut idle_error_logged = false;
+    let mut battery_error_logged = false;
+    // Whether the loop is in failsafe; `status.mode` shows "critical"
+    // instead while above `shutdown_c`.
+    let mut failsafe = false;
+    while !term.load(Ordering::Relaxed) {
+        status.cpu_temp_c = None;
+        status.mem_temp_c = None;
+        status.battery_temp_c = None;
+        status.system_score = None;
+        if let Some(t) = &mut throttle {
+            if t.poll() {
+                if !status.throttling {
+                    log_at!(
+                        Info,
+                        "CPU is throttling; adding {}% to cpu and system fans",
+                        cfg.throttle_bias
+                    );
+                }
+                last_throttle = Some(Instant::now());
+            }
+            let throttling =
+                last_throttle.is_some_and(|t| t.elapsed().as_secs_f64() < cfg.throttle_hold_sec);
+            if status.throttling && !throttling {
+                log_at!(Info, "CPU throttling has stopped; bias removed");
+            }
+            status.throttling = throttling;
+        }
+        if cfg.ambient_reference.is_some() {
+            match ambient.update(&cfg) {
+                Ok(()) => ambient_error_logged = false,
+                Err(e) if !ambient_error_logged => {
+                    log_at!(Warning, "{e}; keeping the last ambient reading");
+                    ambient_error_logged = true;
+                }
+                Err(_) => {}
+            }
+            status.ambient_c = ambient.celsius();
+            status.ambient_shift_c = ambient.shift(&cfg);
+        }
+        if let Some(quiet) = &cfg.quiet {
+            match idle.update(quiet) {
+                Ok(()) => idle_error_logged = false,
+                Err(e) if !idle_error_logged => {
+                    log_at!(Warning, "{e}; using the normal profile");
+                    idle_error_logged = true;
+                }
+                Err(_) => {}
+            }
+            let profile = if idle.quiet() { "quiet" } else { "normal" };
+            if status.profile != profile {
+                log_at!(Info, "switching to the {profile} profile");
+                status.profile = profile.to_string();
+            }
+        }
+        let floor = last_failsafe.and_then(|t| recovery_floor(&cfg, t.elapsed()));
+        // Still in failsafe until it has gone `failsafe_dwell_sec` without an
+        // error, however the cycles in between go.
+        let dwelling = failsafe
+            && last_failsafe.is_some_and(|t| t.elapsed().as_secs_f64() < cfg.failsafe_dwell_sec);
+        let mut dust_event = None;
+        let dt = last_cycle.elapsed().as_secs_f64();
         let result: Result<(), Box<dyn std::error::Error>> = (|| {
-            let cpu_t = max_temp_in_hwmons(&cpu_hwmons)?;
-            let mem_t = max_temp_in_hwmons(&mem_hwmons)?;
-            let cpu_duty = lerp_curve(cpu_t, &cfg.cpu_curve);
-            let mem_duty = lerp_curve(mem_t, &cfg.mem_curve);
-            write_duty(&cfg.fan1_path, cpu_duty, cfg.min_duty, cfg.max_duty)?;
-            write_duty(&cfg.fan2_path, mem_duty, cfg.min_duty, cfg.max_duty)?;
+            let cpu_t = simulate.reading("cpu", || sensor::max_temp(&mut cpu_sensors));
+            let mem_t = simulate.reading("mem", || sensor::max_temp(&mut mem_sensors));
+            // Fed before a failed read fails the cycle, so one broken sensor
+            // does not hide the other being over `shutdown_c`.
+            let read: Vec<f64> = [&cpu_t, &mem_t]
+                .into_iter()
+                .filter_map(|t| t.as_ref().ok().copied())
+                .collect();
+            if let Some(hottest) = read.iter().copied().reduce(f64::max) {
+                critical.update(&cfg, hottest, read.len() == 2);
+            }
+            let (cpu_t, mem_t) = (cpu_t?, mem_t?);
+            status.cpu_temp_c = Some(cpu_t);
+            status.mem_temp_c = Some(mem_t);
+            let now = Instant::now();
+            cpu_window.push(cpu_t);
+            mem_window.push(mem_t);
+            let cpu_eval = filtered(&mut cpu_predictor, &mut cpu_window, now, cpu_t);
+            let mem_eval = filtered(&mut mem_predictor, &mut mem_window, now, mem_t);
+            status.cpu_predicted_c = (cpu_predictor.horizon > 0.0).then_some(cpu_eval);
+            status.mem_predicted_c = (mem_predictor.horizon > 0.0).then_some(mem_eval);
+            let quiet = cfg.quiet.as_ref().filter(|_| idle.quiet());
+            let cpu_curve = quiet
+                .and_then(|q| q.cpu_curve.as_ref())
+                .unwrap_or(&cfg.cpu_curve);
+            let mem_curve = quiet
+                .and_then(|q| q.mem_curve.as_ref())
+                .unwrap_or(&cfg.mem_curve);
+            let cpu_eval = cpu_curve.ambient_shifted(cpu_eval, status.ambient_shift_c);
+            let mem_eval = mem_curve.ambient_shifted(mem_eval, status.ambient_shift_c);
+            // Never during failsafe recovery, which has its own floor.
+            if floor.is_none() {
+                match dust.update(&cfg, &[cpu_t, mem_t]) {
+                    Some(dust::Transition::Started) => {
+                        log_at!(
+                            Info,
+                            "dust clean: running fans at {}% for {}s",
+                            cfg.max_duty,
+                            cfg.dust_clean_sec
+                        );
+                        dust_event = Some("started");
+                    }
+                    Some(dust::Transition::Finished) => {
+                        log_at!(Info, "dust clean: finished");
+                        dust_event = Some("finished");
+                    }
+                    None => {}
+                }
+            }
+            // Like a missing battery, a failing one only drops the floor:
+            // it may have been pulled, and power_supply reads can glitch.
+            let battery_t = if battery_sensors.is_empty() && !simulate.covers("battery") {
+                None
+            } else {
+                match simulate.reading("battery", || sensor::max_temp(&mut battery_sensors)) {
+                    Ok(t) => {
+                        battery_error_logged = false;
+                        Some(t)
+                    }
+                    Err(e) => {
+                        if !battery_error_logged {
+                            log_at!(Warning, "{e}; battery curve not applied");
+                            battery_error_logged = true;
+                        }
+                        None
+                    }
+                }
+            };
+            status.battery_temp_c = battery_t;
+            let cpu_duty = cpu_curve.eval(cpu_eval)?;
+            let mem_duty = mem_curve.eval(mem_eval)?;
+            let battery_duty = match battery_t {
+                Some(t) => cfg.battery_curve.eval(t)?,
+                None => 0,
+            };
+            let system_duty = match &cfg.system {
+                Some(system) => {
+                    let mut temps = vec![("cpu", cpu_eval), ("mem", mem_eval)];
+                    temps.extend(battery_t.map(|t| ("battery", t)));
+                    let score = system.score(&cfg, &temps);
+                    status.system_score = Some(score);
+                    system.curve.eval(score)?
+                }
+                None => cpu_duty,
+            };
+            let curve: Vec<(&str, i32)> = cfg
+                .fans
+                .iter()
+                .map(|f| {
+                    (
+                        f.name.as_str(),
+                        match f.zone.as_str() {
+                            "mem" => mem_duty,
+                            "system" => system_duty,
+                            _ => cpu_duty,
+                        }
+                        .max(battery_duty),
+                    )
+                })
+                .collect();
+            let overrides = match &script {
+                Some(script) => {
+                    let mut temps = vec![("cpu", cpu_t), ("mem", mem_t)];
+                    temps.extend(battery_t.map(|t| ("battery", t)));
+                    temps.extend(status.system_score.map(|s| ("system", s)));
+                    script.run(&temps, &curve)?
+                }
+                None => BTreeMap::new(),
+            };
+            let mut targets: Vec<i32> = status
+                .fans
+                .iter()
+                .zip(&curve)
+                .map(|(fan, (name, curve_duty))| {
+                    let target = overrides.get(*name).copied().unwrap_or(*curve_duty);
+                    if status.throttling && fan.zone != "mem" {
+                        target + cfg.throttle_bias
+                    } else {
+                        target
+                    }
+                })
+                .collect();
+            // Synced fans still smooth on their own, but toward the same
+            // target they converge on the same duty.
+            if cfg.sync_fans {
+                if let Some(&max) = targets.iter().max() {
+                    targets.fill(max);
+                }
+            }
+            for (((output, fan), target), fan_cfg) in outputs
+                .iter_mut()
+                .zip(&mut status.fans)
+                .zip(targets)
+                .zip(&cfg.fans)
+            {
+                if dwelling && fan.firmware_auto && !critical.active() {
+                    continue;
+                }
+                let mut duty = smooth_duty(fan, target, dt, &cfg);
+                duty = stop_dwell(fan, duty, &cfg);
+                // The floor eases down from `failsafe_duty`, which `hold`
+                // and `auto` fans were never at.
+                let floored = matches!(
+                    fan_cfg.failsafe,
+                    FailsafeStrategy::Duty | FailsafeStrategy::Ramp { .. }
+                );
+                if let Some(floor) = floor.filter(|_| floored) {
+                    duty = duty.max(floor);
+                }
+                if dwelling {
+                    duty = duty.max(failsafe_duty(fan_cfg, fan, &escalation, &cfg));
+                }
+                if dust.running() || critical.active() {
+                    duty = cfg.max_duty;
+                }
+                if fan.firmware_auto {
+                    output.force_manual()?;
+                    fan.firmware_auto = false;
+                    log_at!(
+                        Info,
+                        "{}: taken back from firmware automatic control",
+                        fan.name
+                    );
+                }
+                check_reverted(output.as_mut(), fan);
+                write_duty(output.as_mut(), fan, duty, &cfg)?;
+            }
             Ok(())
         })();
 
-        if let Err(e) = result {
-            eprintln!("loop error: {e}; applying failsafe");
-            let _ = write_duty(&cfg.fan1_path, cfg.failsafe_duty, cfg.min_duty, cfg.max_duty);
-            let _ = write_duty(&cfg.fan2_path, cfg.failsafe_duty, cfg.min_duty, cfg.max_duty);
+        let was_failsafe = failsafe;
+        match result {
+            Ok(()) if dwelling => {
+                status.mode = failsafe_mode(&critical);
+            }
+            Ok(()) => {
+                failsafe = false;
+                let failing_sec = escalation.failing_sec();
+                if let Some(level) = escalation.on_success() {
+                    log_at!(Info, "recovered from failsafe escalation level {level}");
+                    escalate::alert(&cfg, "recovered", level, failing_sec, "");
+                }
+                if was_failsafe {
+                    log_at!(
+                        Info,
+                        "recovered; holding failsafe duty for {}s, then ramping down over {}s",
+                        cfg.failsafe_hold_sec,
+                        cfg.failsafe_ramp_sec
+                    );
+                }
+                status.mode = if critical.active() {
+                    "critical".to_string()
+                } else if floor.is_some() {
+                    "recovering".to_string()
+                } else if dust.running() {
+                    "dust-clean".to_string()
+                } else {
+                    "normal".to_string()
+                };
+                status.last_error = None;
+            }
+            Err(e) => {
+                log_at!(Error, "loop error: {e}; applying failsafe");
+                cpu_window.clear();
+                mem_window.clear();
+                last_failsafe = Some(Instant::now());
+                failsafe = true;
+                status.mode = failsafe_mode(&critical);
+                status.last_error = Some(e.to_string());
+                if let Some(level) = escalation.on_error(&cfg) {
+                    log_at!(
+                        Error,
+                        "failsafe for {:.0}s; escalating to level {level}, duty {}",
+                        escalation.failing_sec(),
+                        escalation.duty(&cfg)
+                    );
+                    escalate::alert(
+                        &cfg,
+                        "escalated",
+                        level,
+                        escalation.failing_sec(),
+                        &e.to_string(),
+                    );
+                    let rebound = level >= 2 && !cfg.failsafe_rebind.is_empty();
+                    if rebound {
+                        // Handed back first, so the reopened outputs save
+                        // the driver's own mode and duty rather than ours.
+                        for (output, fan) in outputs.iter_mut().zip(&status.fans) {
+                            if let Err(e) = output.release() {
+                                log_at!(Warning, "{}: release failed: {e}", fan.name);
+                            }
+                        }
+                        for path in &cfg.failsafe_rebind {
+                            match escalate::rebind(path) {
+                                Ok(()) => log_at!(Info, "rebound {path}"),
+                                Err(e) => log_at!(Warning, "cannot rebind {path}: {e}"),
+                            }
+                        }
+                    }
+                    if cfg.failsafe_rediscover || rebound {
+                        match resolve_sensors(&cfg) {
+                            Ok((cpu, mem)) => (cpu_sensors, mem_sensors) = (cpu, mem),
+                            Err(e) => log_at!(Warning, "sensor re-discovery: {e}"),
+                        }
+                        battery_sensors = resolve_battery(&cfg);
+                    }
+                    // Outputs still open are still good; only a rebind
+                    // leaves them pointing at a device that went away.
+                    if rebound {
+                        match open_outputs(&cfg) {
+                            Ok(reopened) => {
+                                outputs = simulate.wrap_outputs(&fan_names(&cfg), reopened);
+                                for fan in &mut status.fans {
+                                    fan.firmware_auto = false;
+                                }
+                            }
+                            Err(e) => log_at!(Warning, "output re-discovery: {e}"),
+                        }
+                    }
+                }
+                for ((output, fan), fan_cfg) in
+                    outputs.iter_mut().zip(&mut status.fans).zip(&cfg.fans)
+                {
+                    fan.smoothed = None;
+                    if !was_failsafe {
+                        fan.failsafe_from = fan.duty;
+                    }
+                    // Over `shutdown_c` a failed cycle must not cool less
+                    // than a good one would.
+                    if critical.active() {
+                        if fan.firmware_auto {
+                            match output.force_manual() {
+                                Ok(_) => fan.firmware_auto = false,
+                                Err(e) => log_at!(
+                                    Warning,
+                                    "{}: cannot take back from firmware control: {e}",
+                                    fan.name
+                                ),
+                            }
+                        }
+                        let _ = write_duty(output.as_mut(), fan, cfg.max_duty, &cfg);
+                        continue;
+                    }
+                    apply_failsafe(
+                        output.as_mut(),
+                        fan,
+                        fan_cfg,
+                        &escalation,
+                        &cfg,
+                        !was_failsafe,
+                    );
+                }
+            }
+        }
+
+        status.updated_unix = unix_now();
+        let sample = Sample {
+            unix: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0),
+            mode: status.mode.clone(),
+            failsafe,
+            temps: vec![status.cpu_temp_c, status.mem_temp_c],
+            duties: status.fans.iter().map(|f| f.duty).collect(),
+        };
+        let now = Instant::now();
+        stats.record(
+            &sample,
+            now.duration_since(last_cycle).as_secs_f64(),
+            failsafe && !was_failsafe,
+            &stats_zones,
+            &stats_fans,
+            &cfg.stats_thresholds,
+        );
+        last_cycle = now;
+        if let Some(db) = &mut sqlite {
+            let mut result = db.sample(&sample);
+            if result.is_ok() && failsafe != was_failsafe {
+                let event = if failsafe { "failsafe" } else { &status.mode };
+                result = db.event(sample.unix, event, status.last_error.as_deref());
+            }
+            if let (Ok(()), Some(what)) = (&result, dust_event) {
+                result = db.event(sample.unix, "dust-clean", Some(what));
+            }
+            match result {
+                Ok(()) => sqlite_error_logged = false,
+                Err(e) if !sqlite_error_logged => {
+                    log_at!(Warning, "sqlite log: {e}");
+                    sqlite_error_logged = true;
+                }
+                Err(_) => {}
+            }
+        }
+        if let Some(a) = &mut archive {
+            match a.record(&sample) {
+                Ok(()) => archive_error_logged = false,
+                Err(e) if !archive_error_logged => {
+                    log_at!(Warning, "cannot write {}: {e}", archive_path(&cfg));
+                    archive_error_logged = true;
+                }
+                Err(_) => {}
+            }
+        }
+        history.lock().unwrap().push(sample);
+        if now.duration_since(last_flush).as_secs_f64() >= STATS_FLUSH_SEC {
+            last_flush = now;
+            stats.prune(cfg.stats_keep_days);
+            let saved = stats
+                .save(&stats_path(&cfg))
+                .map_err(|e| format!("{}: {e}", stats_path(&cfg)))
+                .and_then(|()| {
+                    save_resume(&cfg, &status).map_err(|e| format!("{}: {e}", resume_path(&cfg)))
+                });
+            match saved {
+                Ok(()) => stats_error_logged = false,
+                Err(e) if !stats_error_logged => {
+                    log_at!(Warning, "cannot write {e}");
+                    stats_error_logged = true;
+                }
+                Err(_) => {}
+            }
+        }
+        match write_status(&cfg, &status) {
+            Ok(()) => status_error_logged = false,
+            Err(e) if !status_error_logged => {
+                log_at!(Warning, "cannot write {}: {e}", status_path(&cfg));
+                status_error_logged = true;
+            }
+            Err(_) => {}
+        }
+
+        if let Some(w) = &mut watchdog {
+            match w.pet() {
+                Ok(()) => watchdog_error_logged = false,
+                Err(e) if !watchdog_error_logged => {
+                    log_at!(Error, "cannot pet the watchdog: {e}");
+                    watchdog_error_logged = true;
+                }
+                Err(_) => {}
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs_f64(cfg.poll_sec);
+        // Read errors here are left for the next cycle's own read to report.
+        let mut sample = || {
+            let now = Instant::now();
+            if let Ok(t) = simulate.reading("cpu", || sensor::max_temp(&mut cpu_sensors)) {
+                cpu_predictor.update(now, t);
+                cpu_window.push(t);
+            }
+            if let Ok(t) = simulate.reading("mem", || sensor::max_temp(&mut mem_sensors)) {
+                mem_predictor.update(now, t);
+                mem_window.push(t);
+            }
+        };
+        sleep_until(
+            deadline,
+            &mut outputs,
+            &mut status.fans,
+            &cfg,
+            &term,
+            &mut sample,
+        );
+    }
+
+    log_at!(Info, "shutdown requested, releasing fans");
+    let _ = fs::remove_file(socket_path(&cfg));
+    stats.prune(cfg.stats_keep_days);
+    if let Err(e) = stats.save(&stats_path(&cfg)) {
+        log_at!(Warning, "cannot write {}: {e}", stats_path(&cfg));
+    }
+    if let Err(e) = save_resume(&cfg, &status) {
+        log_at!(Warning, "cannot write {}: {e}", resume_path(&cfg));
+    }
+    if let Some(db) = &mut sqlite {
+        let _ = db.event(unix_now() as f64, "stop", None);
+    }
+    if let Some(a) = &mut archive {
+        if let Err(e) = a.flush() {
+            log_at!(Warning, "cannot write {}: {e}", archive_path(&cfg));
+        }
+    }
+    for (output, fan) in outputs.iter_mut().zip(&status.fans) {
+        if let Err(e) = output.release() {
+            log_at!(Error, "{}: release failed: {e}", fan.name);
+        }
+    }
+    // Disarmed only once the fans are back under firmware control.
+    drop(watchdog);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullOutput;
+
+    impl FanOutput for NullOutput {
+        fn write(&mut self, _duty: i32) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn describe(&self) -> String {
+            "null".to_string()
+        }
+    }
+
+    fn fan_at(cfg: &Config, duty: i32) -> FanStatus {
+        let mut fan = FanStatus::new(&cfg.fans[0], &NullOutput);
+        fan.duty = Some(duty);
+        fan
+    }
+
+    #[test]
+    fn recovery_floor_holds_then_ramps_down_to_min_duty() {
+        let cfg = Config {
+            failsafe_duty: 100,
+            min_duty: 20,
+            failsafe_hold_sec: 10.0,
+            failsafe_ramp_sec: 40.0,
+            ..Config::default()
+        };
+        let floor = |sec| recovery_floor(&cfg, Duration::from_secs(sec));
+        assert_eq!(floor(0), Some(100));
+        assert_eq!(floor(10), Some(100));
+        assert_eq!(floor(30), Some(60));
+        assert_eq!(floor(49), Some(22));
+        assert_eq!(floor(50), None);
+        let cfg = Config {
+            failsafe_ramp_sec: 0.0,
+            ..cfg
+        };
+        assert_eq!(recovery_floor(&cfg, Duration::from_secs(11)), None);
+    }
+
+    #[test]
+    fn smooth_duty_rises_and_falls_with_their_own_time_constants() {
+        let cfg = Config {
+            duty_rise_sec: 2.0,
+            duty_fall_sec: 20.0,
+            ..Config::default()
+        };
+        let mut fan = FanStatus::new(&cfg.fans[0], &NullOutput);
+        // Nothing written yet: straight to the target.
+        assert_eq!(smooth_duty(&mut fan, 30, 1.0, &cfg), 30);
+        fan.duty = Some(30);
+        // One time constant covers 63% of the way.
+        assert_eq!(smooth_duty(&mut fan, 80, 2.0, &cfg), 62);
+        let mut fan = fan_at(&cfg, 80);
+        assert_eq!(smooth_duty(&mut fan, 30, 2.0, &cfg), 75);
+        // Steps too small to change the rounded duty still add up.
+        let mut fan = fan_at(&cfg, 80);
+        assert_eq!(smooth_duty(&mut fan, 79, 1.0, &cfg), 80);
+        for _ in 0..40 {
+            smooth_duty(&mut fan, 79, 1.0, &cfg);
+        }
+        assert_eq!(smooth_duty(&mut fan, 79, 1.0, &cfg), 79);
+    }
+
+    #[test]
+    fn smooth_duty_without_time_constants_jumps() {
+        let cfg = Config::default();
+        let mut fan = fan_at(&cfg, 30);
+        assert_eq!(smooth_duty(&mut fan, 80, 0.1, &cfg), 80);
+        assert_eq!(smooth_duty(&mut fan, 20, 0.1, &cfg), 20);
+    }
+
+    #[test]
+    fn hot_room_shift_tapers_off_over_the_last_segment() {
+        let curve = ZoneCurve::Points(vec![(40.0, 20), (60.0, 50), (80.0, 100)]);
+        assert_eq!(curve.ambient_shifted(50.0, 5.0), 45.0);
+        assert_eq!(curve.ambient_shifted(60.0, 5.0), 55.0);
+        assert_eq!(curve.ambient_shifted(70.0, 5.0), 67.5);
+        assert_eq!(curve.ambient_shifted(80.0, 5.0), 80.0);
+        assert_eq!(curve.ambient_shifted(90.0, 5.0), 90.0);
+        // A cold room shifts toward more cooling everywhere.
+        assert_eq!(curve.ambient_shifted(75.0, -5.0), 80.0);
+        let expr = ZoneCurve::Expr(expr::Expr::parse("t").unwrap());
+        assert_eq!(expr.ambient_shifted(50.0, 5.0), 50.0);
+        assert_eq!(expr.ambient_shifted(50.0, -5.0), 55.0);
+    }
+
+    #[test]
+    fn hot_room_shift_is_continuous_and_monotonic() {
+        let curve = ZoneCurve::Points(vec![(40.0, 20), (60.0, 50), (80.0, 100)]);
+        let duty = |t: f64| curve.eval(curve.ambient_shifted(t, 5.0)).unwrap();
+        // No step where the last segment starts.
+        assert!((duty(60.0) - duty(59.99)).abs() <= 1);
+        assert!((duty(60.01) - duty(60.0)).abs() <= 1);
+        let duties: Vec<i32> = (300..=900).map(|t| duty(f64::from(t) / 10.0)).collect();
+        assert!(duties.windows(2).all(|w| w[1] >= w[0]));
+        assert_eq!(duty(80.0), 100);
+    }
+
+    fn stoppable() -> Config {
+        Config {
+            min_duty: 0,
+            fan_stop_dwell_sec: 30.0,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn stop_dwell_passes_the_first_write_and_running_changes() {
+        let cfg = stoppable();
+        let mut fan = FanStatus::new(&cfg.fans[0], &NullOutput);
+        assert_eq!(stop_dwell(&mut fan, 0, &cfg), 0);
+        let mut fan = fan_at(&cfg, 40);
+        fan.started_or_stopped = Some(Instant::now());
+        assert_eq!(stop_dwell(&mut fan, 60, &cfg), 60);
+        assert_eq!(stop_dwell(&mut fan, 25, &cfg), 25);
+    }
+
+    #[test]
+    fn stop_dwell_holds_a_fresh_state() {
+        let cfg = stoppable();
+        let mut fan = fan_at(&cfg, 40);
+        // Never started or stopped by us: may stop at once.
+        assert_eq!(stop_dwell(&mut fan, 0, &cfg), 0);
+        fan.duty = Some(0);
+        // Just stopped: a restart waits out the dwell.
+        assert_eq!(stop_dwell(&mut fan, 40, &cfg), 0);
+        fan.started_or_stopped = Some(Instant::now() - Duration::from_secs(31));
+        assert_eq!(stop_dwell(&mut fan, 40, &cfg), 40);
+        fan.duty = Some(40);
+        // Just started: held at its running duty rather than stopped.
+        assert_eq!(stop_dwell(&mut fan, 0, &cfg), 40);
+    }
+
+    #[test]
+    fn stop_dwell_needs_a_stoppable_min_duty() {
+        let cfg = Config::default();
+        let mut fan = fan_at(&cfg, 40);
+        fan.started_or_stopped = Some(Instant::now());
+        // min_duty keeps the fan running, so 0 is not a stop.
+        assert_eq!(stop_dwell(&mut fan, 0, &cfg), 0);
+    }
+
+    /// Keeps the last duty written and whether it was handed to firmware.
+    #[derive(Default)]
+    struct Recorder {
+        duty: Option<i32>,
+        has_auto: bool,
+        auto: bool,
+    }
+
+    impl FanOutput for Recorder {
+        fn write(&mut self, duty: i32) -> io::Result<()> {
+            self.duty = Some(duty);
+            Ok(())
+        }
+
+        fn set_auto(&mut self) -> io::Result<bool> {
+            self.auto = self.has_auto;
+            Ok(self.has_auto)
+        }
+
+        fn describe(&self) -> String {
+            "recorder".to_string()
+        }
+    }
+
+    fn strategy(failsafe: FailsafeStrategy) -> FanConfig {
+        FanConfig {
+            failsafe,
+            ..Config::default().fans.remove(0)
         }
+    }
+
+    #[test]
+    fn failsafe_hold_keeps_the_last_duty() {
+        let cfg = Config::default();
+        let escalation = escalate::Escalation::default();
+        let mut fan = fan_at(&cfg, 40);
+        fan.failsafe_from = Some(40);
+        let duty = |s, fan: &FanStatus| failsafe_duty(&strategy(s), fan, &escalation, &cfg);
+        assert_eq!(duty(FailsafeStrategy::Hold, &fan), 40);
+        assert_eq!(duty(FailsafeStrategy::Duty, &fan), 70);
+        // Nothing written before the failure: nothing to hold.
+        fan.failsafe_from = None;
+        assert_eq!(duty(FailsafeStrategy::Hold, &fan), 70);
+    }
+
+    #[test]
+    fn failsafe_ramp_climbs_from_the_last_duty_to_max_duty() {
+        let cfg = Config::default();
+        let mut fan = fan_at(&cfg, 40);
+        fan.failsafe_from = Some(40);
+        let ramp = strategy(FailsafeStrategy::Ramp { sec: 0.001 });
+        let mut escalation = escalate::Escalation::default();
+        assert_eq!(failsafe_duty(&ramp, &fan, &escalation, &cfg), 40);
+        escalation.on_error(&cfg);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(failsafe_duty(&ramp, &fan, &escalation, &cfg), 100);
+    }
+
+    #[test]
+    fn failsafe_escalation_raises_hold() {
+        let cfg = Config {
+            failsafe_escalate_sec: 0.001,
+            ..Config::default()
+        };
+        let mut fan = fan_at(&cfg, 40);
+        fan.failsafe_from = Some(40);
+        let mut escalation = escalate::Escalation::default();
+        escalation.on_error(&cfg);
+        thread::sleep(Duration::from_millis(5));
+        assert!(escalation.on_error(&cfg).is_some());
+        let hold = strategy(FailsafeStrategy::Hold);
+        assert_eq!(failsafe_duty(&hold, &fan, &escalation, &cfg), 100);
+    }
+
+    #[test]
+    fn failsafe_auto_hands_over_or_falls_back_to_failsafe_duty() {
+        let cfg = Config::default();
+        let auto = strategy(FailsafeStrategy::Auto);
+        let escalation = escalate::Escalation::default();
+        let mut output = Recorder {
+            has_auto: true,
+            ..Recorder::default()
+        };
+        let mut fan = fan_at(&cfg, 40);
+        apply_failsafe(&mut output, &mut fan, &auto, &escalation, &cfg, true);
+        assert!(output.auto && fan.firmware_auto);
+        assert_eq!(output.duty, None);
+        let mut output = Recorder::default();
+        let mut fan = fan_at(&cfg, 40);
+        apply_failsafe(&mut output, &mut fan, &auto, &escalation, &cfg, true);
+        assert!(!fan.firmware_auto);
+        assert_eq!(output.duty, Some(70));
+    }
 
-        thread::sleep(Duration::from_secs_f64(cfg.poll_sec));
+    #[test]
+    fn failsafe_auto_needs_firmware_auto_control() {
+        let fan = |backend: &str| {
+            let file: FanFile = toml::from_str(&format!(
+                "name = \"f\"\nbackend = \"{backend}\"\nchip = \"nct6798\"\n\
+                 device = \"smart_device_v2\"\nfailsafe = \"auto\""
+            ))
+            .unwrap();
+            parse_fan(file).map(|f| f.failsafe)
+        };
+        assert_eq!(fan("hwmon"), Ok(FailsafeStrategy::Auto));
+        assert!(fan("gpio_fan").is_err());
+        assert!(fan("pwm_fan").is_err());
+        assert!(fan("usb_hid").is_err());
     }
 }
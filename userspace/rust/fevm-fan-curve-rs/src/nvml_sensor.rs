@@ -0,0 +1,88 @@
+use crate::sensor::SensorSource;
+use serde::Deserialize;
+
+/// Which GPU: its NVML index, or its UUID (`nvidia-smi -L`), which stays
+/// the same when an eGPU is plugged in after another card.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum GpuId {
+    Index(u32),
+    Uuid(String),
+}
+
+impl std::fmt::Display for GpuId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(i) => write!(f, "{i}"),
+            Self::Uuid(uuid) => f.write_str(uuid),
+        }
+    }
+}
+
+/// An NVIDIA GPU's core temperature, read through NVML (libnvidia-ml.so,
+/// loaded at runtime from the proprietary driver). Only available when
+/// built with `--features nvml`.
+#[cfg(feature = "nvml")]
+pub struct NvmlSensor {
+    name: String,
+    gpu: GpuId,
+    nvml: nvml_wrapper::Nvml,
+}
+
+#[cfg(feature = "nvml")]
+impl NvmlSensor {
+    pub fn open(name: &str, gpu: &GpuId) -> Result<Self, Box<dyn std::error::Error>> {
+        let nvml = nvml_wrapper::Nvml::init().map_err(|e| format!("NVML: {e}"))?;
+        let sensor = Self {
+            name: name.to_string(),
+            gpu: gpu.clone(),
+            nvml,
+        };
+        // Fails loading, rather than the first cycle, on a wrong id.
+        sensor.device()?;
+        Ok(sensor)
+    }
+
+    fn device(&self) -> Result<nvml_wrapper::Device<'_>, Box<dyn std::error::Error>> {
+        let device = match &self.gpu {
+            GpuId::Index(i) => self.nvml.device_by_index(*i),
+            GpuId::Uuid(uuid) => self.nvml.device_by_uuid(uuid.as_str()),
+        };
+        device.map_err(|e| format!("GPU {}: {e}", self.gpu).into())
+    }
+}
+
+#[cfg(feature = "nvml")]
+impl SensorSource for NvmlSensor {
+    fn read(&mut self) -> Result<f64, Box<dyn std::error::Error>> {
+        let t = self
+            .device()?
+            .temperature(nvml_wrapper::enum_wrappers::device::TemperatureSensor::Gpu)?;
+        Ok(f64::from(t))
+    }
+
+    fn describe(&self) -> String {
+        format!("nvml:{}", self.name)
+    }
+}
+
+#[cfg(not(feature = "nvml"))]
+pub struct NvmlSensor;
+
+#[cfg(not(feature = "nvml"))]
+impl NvmlSensor {
+    pub fn open(_name: &str, _gpu: &GpuId) -> Result<Self, Box<dyn std::error::Error>> {
+        Err("sensors.nvml needs a build with the nvml feature".into())
+    }
+}
+
+#[cfg(not(feature = "nvml"))]
+impl SensorSource for NvmlSensor {
+    fn read(&mut self) -> Result<f64, Box<dyn std::error::Error>> {
+        Err("built without the nvml feature".into())
+    }
+
+    fn describe(&self) -> String {
+        "nvml".to_string()
+    }
+}
@@ -0,0 +1,312 @@
+use crate::history::History;
+use crate::{clamp_duty, Config, ZoneCurve};
+use std::fmt::Write;
+
+const WIDTH: f64 = 800.0;
+const PANEL_HEIGHT: f64 = 420.0;
+const MARGIN: (f64, f64, f64, f64) = (60.0, 20.0, 40.0, 50.0); // left, right, top, bottom
+const COLORS: [&str; 8] = [
+    "#d62728", "#1f77b4", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+
+/// Maps data coordinates into one panel of the drawing.
+struct Panel {
+    top: f64,
+    x: (f64, f64),
+    y: (f64, f64),
+}
+
+impl Panel {
+    fn px(&self, x: f64) -> f64 {
+        let w = WIDTH - MARGIN.0 - MARGIN.1;
+        MARGIN.0 + (x - self.x.0) / (self.x.1 - self.x.0) * w
+    }
+
+    fn py(&self, y: f64) -> f64 {
+        let h = PANEL_HEIGHT - MARGIN.2 - MARGIN.3;
+        self.top + MARGIN.2 + h - (y - self.y.0) / (self.y.1 - self.y.0) * h
+    }
+
+    /// Frame, grid, tick labels and titles.
+    fn axes(&self, out: &mut String, title: &str, x_label: &str, y_label: &str, x_step: f64) {
+        let (x0, x1) = (self.px(self.x.0), self.px(self.x.1));
+        let (y0, y1) = (self.py(self.y.0), self.py(self.y.1));
+        let _ = writeln!(
+            out,
+            r##"<text x="{:.1}" y="{:.1}" font-size="16" text-anchor="middle">{}</text>"##,
+            WIDTH / 2.0,
+            self.top + 24.0,
+            escape(title)
+        );
+        let mut x = (self.x.0 / x_step).ceil() * x_step;
+        while x <= self.x.1 + 1e-9 {
+            let px = self.px(x);
+            let _ = writeln!(
+                out,
+                r##"<line x1="{px:.1}" y1="{y0:.1}" x2="{px:.1}" y2="{y1:.1}" stroke="#ddd"/>"##
+            );
+            let _ = writeln!(
+                out,
+                r##"<text x="{px:.1}" y="{:.1}" font-size="11" text-anchor="middle">{}</text>"##,
+                y0 + 15.0,
+                (x * 1000.0).round() / 1000.0
+            );
+            x += x_step;
+        }
+        let mut y = (self.y.0 / 10.0).ceil() * 10.0;
+        while y <= self.y.1 + 1e-9 {
+            let py = self.py(y);
+            let _ = writeln!(
+                out,
+                r##"<line x1="{x0:.1}" y1="{py:.1}" x2="{x1:.1}" y2="{py:.1}" stroke="#ddd"/>"##
+            );
+            let _ = writeln!(
+                out,
+                r##"<text x="{:.1}" y="{:.1}" font-size="11" text-anchor="end">{y}</text>"##,
+                x0 - 6.0,
+                py + 4.0
+            );
+            y += 10.0;
+        }
+        let _ = writeln!(
+            out,
+            concat!(
+                r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}""##,
+                r##" fill="none" stroke="#000"/>"##
+            ),
+            x0,
+            y1,
+            x1 - x0,
+            y0 - y1
+        );
+        let _ = writeln!(
+            out,
+            r##"<text x="{:.1}" y="{:.1}" font-size="12" text-anchor="middle">{}</text>"##,
+            (x0 + x1) / 2.0,
+            y0 + 34.0,
+            escape(x_label)
+        );
+        let _ = writeln!(
+            out,
+            concat!(
+                r##"<text x="16" y="{0:.1}" font-size="12" text-anchor="middle""##,
+                r##" transform="rotate(-90 16 {0:.1})">{1}</text>"##
+            ),
+            (y0 + y1) / 2.0,
+            escape(y_label)
+        );
+    }
+
+    fn polyline(&self, out: &mut String, points: &[(f64, f64)], color: &str, dash: bool) {
+        if points.len() < 2 {
+            return;
+        }
+        let coords: Vec<String> = points
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", self.px(*x), self.py(*y)))
+            .collect();
+        let _ = writeln!(
+            out,
+            r##"<polyline points="{}" fill="none" stroke="{color}" stroke-width="2"{}/>"##,
+            coords.join(" "),
+            if dash {
+                r#" stroke-dasharray="6 4""#
+            } else {
+                ""
+            }
+        );
+    }
+
+    /// Legend entries stacked in the top-left corner of the plot area.
+    fn legend(&self, out: &mut String, entries: &[(String, &str)]) {
+        for (n, (label, color)) in entries.iter().enumerate() {
+            let x = self.px(self.x.0) + 10.0;
+            let y = self.py(self.y.1) + 16.0 + n as f64 * 16.0;
+            let _ = writeln!(
+                out,
+                r##"<rect x="{x:.1}" y="{:.1}" width="12" height="4" fill="{color}"/>"##,
+                y - 4.0
+            );
+            let _ = writeln!(
+                out,
+                r##"<text x="{:.1}" y="{y:.1}" font-size="12">{}</text>"##,
+                x + 18.0,
+                escape(label)
+            );
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The duty a curve gives after the min/max clamp, every half degree.
+fn sample_curve(curve: &ZoneCurve, cfg: &Config, range: (f64, f64)) -> Vec<(f64, f64)> {
+    let steps = ((range.1 - range.0) * 2.0).round() as usize;
+    (0..=steps)
+        .filter_map(|i| {
+            let t = range.0 + i as f64 * 0.5;
+            let duty = curve.eval(t).ok()?;
+            Some((t, f64::from(clamp_duty(duty, cfg.min_duty, cfg.max_duty))))
+        })
+        .collect()
+}
+
+/// Renders the zone curves, and with a trace the (temperature, duty) pairs
+/// it recorded on top of them plus a second panel of both over time.
+pub fn render_svg(cfg: &Config, trace: Option<&History>) -> String {
+    let zones: [(&str, &ZoneCurve); 2] = [("cpu", &cfg.cpu_curve), ("mem", &cfg.mem_curve)];
+    let mut t_range = (20.0f64, 100.0f64);
+    for (_, curve) in &zones {
+        if let ZoneCurve::Points(points) = curve {
+            for (t, _) in points {
+                t_range = (t_range.0.min(t - 5.0), t_range.1.max(t + 5.0));
+            }
+        }
+    }
+    t_range = (
+        (t_range.0 / 10.0).floor() * 10.0,
+        (t_range.1 / 10.0).ceil() * 10.0,
+    );
+
+    let panels = if trace.is_some_and(|h| h.samples().next().is_some()) {
+        2.0
+    } else {
+        1.0
+    };
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}""#,
+            r#" font-family="sans-serif">"#
+        ),
+        WIDTH,
+        PANEL_HEIGHT * panels
+    );
+    let _ = writeln!(out, r##"<rect width="100%" height="100%" fill="#fff"/>"##);
+
+    let curves = Panel {
+        top: 0.0,
+        x: t_range,
+        y: (0.0, 100.0),
+    };
+    curves.axes(&mut out, "Fan curves", "temperature (°C)", "duty (%)", 10.0);
+    let mut legend = Vec::new();
+    for (n, (zone, curve)) in zones.iter().enumerate() {
+        let color = COLORS[n % COLORS.len()];
+        curves.polyline(&mut out, &sample_curve(curve, cfg, t_range), color, false);
+        let fans: Vec<&str> = cfg
+            .fans
+            .iter()
+            .filter(|f| f.zone == *zone)
+            .map(|f| f.name.as_str())
+            .collect();
+        let label = if fans.is_empty() {
+            format!("{zone} curve")
+        } else {
+            format!("{zone} curve ({})", fans.join(", "))
+        };
+        legend.push((label, color));
+    }
+
+    let Some(trace) = trace.filter(|_| panels > 1.0) else {
+        curves.legend(&mut out, &legend);
+        out.push_str("</svg>\n");
+        return out;
+    };
+
+    // Recorded duty against the temperature of the zone each fan follows.
+    for (f, fan) in trace.fans().iter().enumerate() {
+        let zone = cfg
+            .fans
+            .iter()
+            .find(|c| &c.name == fan)
+            .map(|c| c.zone.as_str())
+            .unwrap_or("cpu");
+        let Some(z) = trace.zones().iter().position(|name| name == zone) else {
+            continue;
+        };
+        let color = COLORS[(2 + f) % COLORS.len()];
+        for s in trace.samples() {
+            let (Some(t), Some(d)) = (s.temps[z], s.duties[f]) else {
+                continue;
+            };
+            if t < t_range.0 || t > t_range.1 {
+                continue;
+            }
+            let _ = writeln!(
+                out,
+                r##"<circle cx="{:.1}" cy="{:.1}" r="2" fill="{color}" fill-opacity="0.4"/>"##,
+                curves.px(t),
+                curves.py(f64::from(d))
+            );
+        }
+        legend.push((format!("{fan} recorded"), color));
+    }
+    curves.legend(&mut out, &legend);
+
+    let samples: Vec<_> = trace.samples().collect();
+    let start = samples[0].unix;
+    let span = (samples[samples.len() - 1].unix - start).max(1.0);
+    let (unit, scale) = if span > 7200.0 {
+        ("h", 3600.0)
+    } else if span > 120.0 {
+        ("min", 60.0)
+    } else {
+        ("s", 1.0)
+    };
+    let y_max = samples
+        .iter()
+        .flat_map(|s| s.temps.iter().flatten())
+        .fold(100.0f64, |a, b| a.max(*b));
+    let timeline = Panel {
+        top: PANEL_HEIGHT,
+        x: (0.0, span / scale),
+        y: (0.0, (y_max / 10.0).ceil() * 10.0),
+    };
+    let x_step = nice_step(span / scale);
+    timeline.axes(
+        &mut out,
+        "Recorded trace",
+        &format!("time ({unit})"),
+        "°C / duty (%)",
+        x_step,
+    );
+    let mut legend = Vec::new();
+    for (z, zone) in trace.zones().iter().enumerate() {
+        let color = COLORS[z % COLORS.len()];
+        let line: Vec<(f64, f64)> = samples
+            .iter()
+            .filter_map(|s| Some(((s.unix - start) / scale, s.temps[z]?)))
+            .collect();
+        timeline.polyline(&mut out, &line, color, true);
+        legend.push((format!("{zone} °C"), color));
+    }
+    for (f, fan) in trace.fans().iter().enumerate() {
+        let color = COLORS[(2 + f) % COLORS.len()];
+        let line: Vec<(f64, f64)> = samples
+            .iter()
+            .filter_map(|s| Some(((s.unix - start) / scale, f64::from(s.duties[f]?))))
+            .collect();
+        timeline.polyline(&mut out, &line, color, false);
+        legend.push((format!("{fan} %"), color));
+    }
+    timeline.legend(&mut out, &legend);
+    out.push_str("</svg>\n");
+    out
+}
+
+/// A round grid step giving about 5 to 10 lines over `span`.
+fn nice_step(span: f64) -> f64 {
+    let raw = span / 8.0;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
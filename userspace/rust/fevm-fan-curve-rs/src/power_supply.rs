@@ -0,0 +1,43 @@
+use crate::sensor::SensorSource;
+use std::fs;
+use std::path::PathBuf;
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// The `temp` attribute of a power supply (a battery such as BAT0, or a
+/// charger such as ACAD), which the kernel reports in tenths of °C.
+pub struct PowerSupplySource {
+    name: String,
+    path: PathBuf,
+}
+
+impl PowerSupplySource {
+    /// Opens `name` under /sys/class/power_supply, failing when it does
+    /// not exist or reports no temperature.
+    pub fn open(name: &str) -> Result<Self, String> {
+        let dir = PathBuf::from(POWER_SUPPLY_DIR).join(name);
+        if !dir.exists() {
+            return Err(format!("power supply {name} not found"));
+        }
+        let path = dir.join("temp");
+        if !path.exists() {
+            return Err(format!("power supply {name} has no temperature"));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            path,
+        })
+    }
+}
+
+impl SensorSource for PowerSupplySource {
+    fn read(&mut self) -> Result<f64, Box<dyn std::error::Error>> {
+        let raw = fs::read_to_string(&self.path)?;
+        let tenths: f64 = raw.trim().parse()?;
+        Ok(tenths / 10.0)
+    }
+
+    fn describe(&self) -> String {
+        format!("power_supply:{}", self.name)
+    }
+}
@@ -0,0 +1,345 @@
+use crate::history::History;
+use crate::{clamp_duty, smooth_step, Config, Predictor};
+use std::time::{Duration, Instant};
+
+/// Duty change that counts as an audible step.
+const STEP_PCT: i32 = 5;
+/// Duty bands of the noise profile, upper bounds.
+const BANDS: [i32; 4] = [30, 50, 70, 90];
+
+/// What one fan did over the trace.
+#[derive(Default)]
+struct Summary {
+    seconds: f64,
+    weighted: f64,
+    /// (duty, seconds) for percentiles.
+    duties: Vec<(i32, f64)>,
+    bands: [f64; BANDS.len() + 1],
+    steps: u32,
+    /// Time-weighted sum of 10^(L/10), L the level relative to 100% duty.
+    energy: f64,
+    last: Option<i32>,
+}
+
+impl Summary {
+    fn add(&mut self, duty: i32, secs: f64) {
+        self.seconds += secs;
+        self.weighted += f64::from(duty) * secs;
+        self.duties.push((duty, secs));
+        let band = BANDS.iter().position(|b| duty < *b).unwrap_or(BANDS.len());
+        self.bands[band] += secs;
+        if self.last.is_some_and(|l| (duty - l).abs() >= STEP_PCT) {
+            self.steps += 1;
+        }
+        if self.last.is_none_or(|l| (duty - l).abs() >= STEP_PCT) {
+            self.last = Some(duty);
+        }
+        // Fan noise goes roughly with 50 log10 of speed.
+        let level = 50.0 * (f64::from(duty.max(1)) / 100.0).log10();
+        self.energy += 10f64.powf(level / 10.0) * secs;
+    }
+
+    fn percentile(&self, p: f64) -> i32 {
+        let mut sorted = self.duties.clone();
+        sorted.sort_by_key(|(d, _)| *d);
+        let mut acc = 0.0;
+        for (d, secs) in &sorted {
+            acc += secs;
+            if acc >= self.seconds * p {
+                return *d;
+            }
+        }
+        sorted.last().map_or(0, |(d, _)| *d)
+    }
+
+    fn row(&self) -> Vec<String> {
+        let share = |secs: f64| format!("{:.0}%", secs * 100.0 / self.seconds.max(1e-9));
+        let mut row = vec![
+            format!("{:.1}", self.weighted / self.seconds.max(1e-9)),
+            self.percentile(0.5).to_string(),
+            self.percentile(0.95).to_string(),
+            self.duties
+                .iter()
+                .map(|(d, _)| *d)
+                .max()
+                .unwrap_or(0)
+                .to_string(),
+            format!("{:.1}", self.steps as f64 * 3600.0 / self.seconds.max(1e-9)),
+            format!(
+                "{:.1}",
+                10.0 * (self.energy / self.seconds.max(1e-9)).log10()
+            ),
+        ];
+        row.extend(self.bands.iter().map(|s| share(*s)));
+        row
+    }
+}
+
+/// How long each sample stands for: the gap to the next one, capped at
+/// twice the usual gap so a stretch where the daemon was stopped does not
+/// count.
+fn weights(trace: &History) -> Vec<f64> {
+    let times: Vec<f64> = trace.samples().map(|s| s.unix).collect();
+    let mut gaps: Vec<f64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+    let mut sorted = gaps.clone();
+    sorted.sort_by(f64::total_cmp);
+    let usual = sorted.get(sorted.len() / 2).copied().unwrap_or(1.0);
+    gaps.push(usual);
+    gaps.iter().map(|g| g.clamp(0.0, usual * 2.0)).collect()
+}
+
+/// Runs `zone`'s curve from `cfg` over a recorded trace and reports how
+/// the fans would have behaved next to what was recorded. The
+/// temperatures are the recorded ones: a different curve would have
+/// changed them, so this compares fan behavior, not cooling.
+pub fn report(cfg: &Config, trace: &History, zone: &str) -> Result<String, String> {
+    let column = |name: &str| trace.zones().iter().position(|z| z == name);
+    let zone_cfg = cfg.zones.iter().find(|z| z.name == zone);
+    let (curve, inputs) = match (zone, &cfg.system) {
+        ("system", Some(system)) => (
+            &system.curve,
+            system
+                .weights
+                .iter()
+                .map(|(z, _)| column(z).map(|c| (z.as_str(), c)))
+                .collect::<Option<Vec<_>>>()
+                .ok_or("trace lacks a zone the system zone weighs")?,
+        ),
+        ("system", None) => return Err("the config has no curves.system".to_string()),
+        ("cpu" | "mem", _) => (
+            if zone == "cpu" {
+                &cfg.cpu_curve
+            } else {
+                &cfg.mem_curve
+            },
+            vec![(
+                zone,
+                column(zone).ok_or(format!("trace has no {zone}_c column"))?,
+            )],
+        ),
+        _ => return Err(format!("--zone must be cpu, mem or system, got {zone}")),
+    };
+    let recorded_fan = cfg.fans.iter().filter(|f| f.zone == zone).find_map(|f| {
+        trace
+            .fans()
+            .iter()
+            .position(|n| *n == f.name)
+            .map(|i| (i, &f.name))
+    });
+
+    let mut predictor = zone_cfg.map(Predictor::new);
+    let base = Instant::now();
+    let start = trace.samples().next().map_or(0.0, |s| s.unix);
+    let mut recorded = Summary::default();
+    let mut candidate = Summary::default();
+    let mut thresholds = vec![0.0; cfg.stats_thresholds.len()];
+    let mut smoothed: Option<f64> = None;
+    let mut prev_unix: Option<f64> = None;
+    let mut skipped = 0;
+    for (sample, secs) in trace.samples().zip(weights(trace)) {
+        let temps: Option<Vec<(&str, f64)>> = inputs
+            .iter()
+            .map(|(name, c)| sample.temps[*c].map(|t| (*name, t)))
+            .collect();
+        let Some(temps) = temps else {
+            skipped += 1;
+            continue;
+        };
+        let t = match (zone, &cfg.system) {
+            ("system", Some(system)) => system.score(cfg, &temps),
+            _ => {
+                let now = base + Duration::from_secs_f64((sample.unix - start).max(0.0));
+                let t = temps[0].1;
+                for (threshold, acc) in cfg.stats_thresholds.iter().zip(&mut thresholds) {
+                    if t >= *threshold {
+                        *acc += secs;
+                    }
+                }
+                match &mut predictor {
+                    Some(p) => p.update(now, t),
+                    None => t,
+                }
+            }
+        };
+        let target = match curve.eval(t) {
+            Ok(duty) => f64::from(duty),
+            Err(_) => f64::from(cfg.failsafe_duty),
+        };
+        let dt = prev_unix.map_or(0.0, |p| sample.unix - p);
+        prev_unix = Some(sample.unix);
+        let next = smoothed.map_or(target, |prev| smooth_step(prev, target, dt, cfg));
+        smoothed = Some(next);
+        candidate.add(
+            clamp_duty(next.round() as i32, cfg.min_duty, cfg.max_duty),
+            secs,
+        );
+        if let Some(duty) = recorded_fan.and_then(|(i, _)| sample.duties[i]) {
+            recorded.add(duty, secs);
+        }
+    }
+    if candidate.seconds <= 0.0 {
+        return Err(format!("trace has no usable {zone} samples"));
+    }
+
+    let mut out = format!(
+        "{} samples covering {:.1}h, zone {zone}",
+        candidate.duties.len(),
+        candidate.seconds / 3600.0
+    );
+    if skipped > 0 {
+        out += &format!(" ({skipped} without a reading skipped)");
+    }
+    out.push('\n');
+    if zone != "system" && !thresholds.is_empty() {
+        let above: Vec<String> = cfg
+            .stats_thresholds
+            .iter()
+            .zip(&thresholds)
+            .map(|(t, secs)| format!(">={t}C {:.1}%", secs * 100.0 / candidate.seconds))
+            .collect();
+        out += &format!("time above (as recorded): {}\n", above.join("  "));
+    }
+    let header = [
+        "", "mean", "p50", "p95", "max", "steps/h", "dB", "<30", "30-50", "50-70", "70-90", ">=90",
+    ];
+    let mut rows = vec![header.map(String::from).to_vec()];
+    if let Some((_, name)) = recorded_fan.filter(|_| recorded.seconds > 0.0) {
+        let mut row = vec![format!("recorded ({name})")];
+        row.extend(recorded.row());
+        rows.push(row);
+    }
+    let mut row = vec!["candidate".to_string()];
+    row.extend(candidate.row());
+    rows.push(row);
+    let mut widths = vec![0; header.len()];
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, w))| match i {
+                0 => format!("{cell:<w$}"),
+                _ => format!("{cell:>w$}"),
+            })
+            .collect();
+        out += cells.join("  ").trim_end();
+        out.push('\n');
+    }
+    out += &format!(
+        "steps/h: duty changes of {STEP_PCT}% or more, per hour; \
+         dB: time-averaged fan noise relative to 100% duty\n"
+    );
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(rows: &[&str]) -> History {
+        let mut csv = "unix,mode,cpu_c,mem_c,fan1,fan2\n".to_string();
+        for row in rows {
+            csv += row;
+            csv.push('\n');
+        }
+        History::from_csv(&csv).unwrap()
+    }
+
+    #[test]
+    fn summary_is_time_weighted() {
+        let mut s = Summary::default();
+        s.add(50, 30.0);
+        s.add(80, 10.0);
+        assert_eq!(s.seconds, 40.0);
+        assert_eq!(s.weighted / s.seconds, 57.5);
+        assert_eq!(s.percentile(0.5), 50);
+        assert_eq!(s.percentile(0.95), 80);
+        assert_eq!(s.bands, [0.0, 0.0, 30.0, 10.0, 0.0]);
+        assert_eq!(s.steps, 1);
+    }
+
+    #[test]
+    fn small_changes_are_not_steps_until_they_add_up() {
+        let mut s = Summary::default();
+        for duty in [50, 52, 54, 56, 57, 50] {
+            s.add(duty, 1.0);
+        }
+        // 50 -> 56 and 56 -> 50; the moves in between are under STEP_PCT.
+        assert_eq!(s.steps, 2);
+    }
+
+    #[test]
+    fn noise_level_is_relative_to_full_duty() {
+        let mut s = Summary::default();
+        s.add(100, 5.0);
+        assert_eq!(s.row()[5], "0.0");
+        let mut s = Summary::default();
+        s.add(50, 5.0);
+        // 50 log10(0.5)
+        assert_eq!(s.row()[5], "-15.1");
+    }
+
+    #[test]
+    fn gaps_are_capped_at_twice_the_usual() {
+        let t = trace(&[
+            "0,normal,60,40,50,30",
+            "1,normal,60,40,50,30",
+            "2,normal,60,40,50,30",
+            "3,normal,60,40,50,30",
+            "100,normal,60,40,50,30",
+        ]);
+        assert_eq!(weights(&t), [1.0, 1.0, 1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn candidate_follows_the_curve() {
+        let cfg = Config {
+            duty_rise_sec: 0.0,
+            duty_fall_sec: 0.0,
+            ..Config::default()
+        };
+        let t = trace(&[
+            "0,normal,65,40,50,30",
+            "1,normal,65,40,50,30",
+            "2,normal,75,40,80,30",
+            "3,normal,75,40,80,30",
+            "4,normal,,40,80,30",
+        ]);
+        let out = report(&cfg, &t, "cpu").unwrap();
+        assert!(out.starts_with("4 samples covering 0.0h, zone cpu (1 without a reading skipped)"));
+        let line = |prefix: &str| {
+            out.lines()
+                .find(|l| l.starts_with(prefix))
+                .unwrap()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+        };
+        // mean, p50, p95, max of the default curve at 65C and 75C.
+        assert_eq!(line("candidate")[1..5], ["65.0", "55", "75", "75"]);
+        assert_eq!(line("recorded (fan1)")[2..6], ["65.0", "50", "80", "80"]);
+    }
+
+    #[test]
+    fn report_errors() {
+        let cfg = Config::default();
+        let t = trace(&["0,normal,60,40,50,30"]);
+        assert_eq!(
+            report(&cfg, &t, "gpu").unwrap_err(),
+            "--zone must be cpu, mem or system, got gpu"
+        );
+        assert_eq!(
+            report(&cfg, &t, "system").unwrap_err(),
+            "the config has no curves.system"
+        );
+        let t = trace(&["0,normal,,40,50,30"]);
+        assert_eq!(
+            report(&cfg, &t, "cpu").unwrap_err(),
+            "trace has no usable cpu samples"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// Saved state older than this is ignored on start: the machine has been
+/// off or idle long enough that the old duties say nothing about now.
+pub const MAX_AGE_SEC: u64 = 600;
+
+/// What the daemon was doing when it last saved, so a restart picks up
+/// where it left off instead of starting the fans from scratch.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Saved {
+    pub saved_unix: u64,
+    pub mode: String,
+    /// "normal", or "quiet" while the idle profile was in use.
+    #[serde(default)]
+    pub profile: String,
+    /// Last duty written to each fan, by name.
+    #[serde(default)]
+    pub duties: BTreeMap<String, i32>,
+}
+
+impl Saved {
+    /// The saved state, or None when there is none or it is too old.
+    pub fn load(path: &str, now_unix: u64) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let saved: Self = toml::from_str(&raw)?;
+        Ok((now_unix.saturating_sub(saved.saved_unix) <= MAX_AGE_SEC).then_some(saved))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = format!("{path}.tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn age_check() {
+        let path = std::env::temp_dir()
+            .join(format!("fevm-resume-{}.toml", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let _ = fs::remove_file(&path);
+        assert!(Saved::load(&path, 1000).unwrap().is_none());
+
+        let saved = Saved {
+            saved_unix: 1000,
+            mode: "normal".to_string(),
+            profile: "quiet".to_string(),
+            duties: BTreeMap::from([("fan1".to_string(), 42)]),
+        };
+        saved.save(&path).unwrap();
+        let loaded = Saved::load(&path, 1000 + MAX_AGE_SEC).unwrap().unwrap();
+        assert_eq!(loaded.duties, saved.duties);
+        assert_eq!(loaded.profile, "quiet");
+        assert!(Saved::load(&path, 1001 + MAX_AGE_SEC).unwrap().is_none());
+        // A clock that went backwards is not "too old".
+        assert!(Saved::load(&path, 10).unwrap().is_some());
+
+        fs::write(&path, "saved_unix = \"x\"").unwrap();
+        assert!(Saved::load(&path, 1000).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+/// Upper bound on the work one `control` call may do, so a runaway loop in
+/// a script turns into an error (and failsafe) instead of a hung daemon.
+#[cfg(feature = "rhai")]
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A user Rhai script defining
+///
+/// ```text
+/// fn control(temps, curve) { ... }
+/// ```
+///
+/// `temps` maps cpu, mem and, when present, battery to °C, and system to
+/// its 0..100 score when `curves.system` is set; `curve` maps fan names to
+/// the duty the configured curves picked. It returns a map of fan name to
+/// duty; fans it leaves out keep their curve duty. Only available when
+/// built with `--features rhai`.
+#[cfg(feature = "rhai")]
+pub struct Script {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+    fans: Vec<String>,
+}
+
+#[cfg(feature = "rhai")]
+impl Script {
+    pub fn load(path: &str, fans: Vec<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| format!("{path}: {e}"))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "control" && f.params.len() == 2)
+        {
+            return Err(format!("{path}: no fn control(temps, curve)").into());
+        }
+        Ok(Self { engine, ast, fans })
+    }
+
+    pub fn run(
+        &self,
+        temps: &[(&str, f64)],
+        curve: &[(&str, i32)],
+    ) -> Result<BTreeMap<String, i32>, String> {
+        let temps: rhai::Map = temps
+            .iter()
+            .map(|(k, v)| ((*k).into(), rhai::Dynamic::from_float(*v)))
+            .collect();
+        let curve: rhai::Map = curve
+            .iter()
+            .map(|(k, v)| ((*k).into(), rhai::Dynamic::from_int(i64::from(*v))))
+            .collect();
+        let mut scope = rhai::Scope::new();
+        let out: rhai::Map = self
+            .engine
+            .call_fn(&mut scope, &self.ast, "control", (temps, curve))
+            .map_err(|e| format!("script: {e}"))?;
+
+        let mut duties = BTreeMap::new();
+        for (fan, value) in out {
+            if !self.fans.iter().any(|f| f == fan.as_str()) {
+                return Err(format!("script: returned a duty for unknown fan '{fan}'"));
+            }
+            let duty = if let Ok(v) = value.as_int() {
+                v as f64
+            } else if let Ok(v) = value.as_float() {
+                v
+            } else {
+                return Err(format!(
+                    "script: duty for {fan} is a {}, not a number",
+                    value.type_name()
+                ));
+            };
+            if !duty.is_finite() {
+                return Err(format!("script: duty for {fan} is {duty}"));
+            }
+            duties.insert(fan.to_string(), duty.clamp(0.0, 100.0).round() as i32);
+        }
+        Ok(duties)
+    }
+}
+
+#[cfg(not(feature = "rhai"))]
+pub struct Script;
+
+#[cfg(not(feature = "rhai"))]
+impl Script {
+    pub fn load(_path: &str, _fans: Vec<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        Err("general.script needs a build with the rhai feature".into())
+    }
+
+    pub fn run(
+        &self,
+        _temps: &[(&str, f64)],
+        _curve: &[(&str, i32)],
+    ) -> Result<BTreeMap<String, i32>, String> {
+        Ok(BTreeMap::new())
+    }
+}
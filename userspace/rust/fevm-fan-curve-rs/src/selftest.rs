@@ -0,0 +1,249 @@
+use crate::{
+    clamp_duty, lock_instance, open_outputs, resolve_sensors, status_path, write_duty, Config,
+    FanStatus,
+};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long each duty step is held before the tach is read.
+const SETTLE_SEC: u64 = 6;
+/// Readings outside this range are not a working temperature sensor.
+const PLAUSIBLE_C: (f64, f64) = (5.0, 115.0);
+/// Largest believable change between two readings one second apart.
+const MAX_JUMP_C: f64 = 15.0;
+
+enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+}
+
+struct Report {
+    lines: Vec<(Verdict, String)>,
+}
+
+impl Report {
+    fn add(&mut self, verdict: Verdict, msg: String) {
+        let tag = match verdict {
+            Verdict::Pass => "PASS",
+            Verdict::Warn => "WARN",
+            Verdict::Fail => "FAIL",
+            Verdict::Skip => "SKIP",
+        };
+        println!("  {tag} {msg}");
+        self.lines.push((verdict, msg));
+    }
+
+    fn failures(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|(v, _)| matches!(v, Verdict::Fail))
+            .count()
+    }
+}
+
+fn read_rpm(path: &str) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// The pid in status.toml, if that process is still alive.
+pub fn running_daemon(cfg: &Config) -> Option<u32> {
+    let raw = fs::read_to_string(status_path(cfg)).ok()?;
+    let status: toml::Value = toml::from_str(&raw).ok()?;
+    let pid = u32::try_from(status.get("pid")?.as_integer()?).ok()?;
+    (pid != std::process::id() && Path::new(&format!("/proc/{pid}")).exists()).then_some(pid)
+}
+
+/// Sweeps every fan over a few duties, checking write-back and (when
+/// `rpm_path` is set) that the tach follows, while watching the zone
+/// sensors for implausible values. Returns an error if any check failed.
+pub fn run(cfg: &Config, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(pid) = running_daemon(cfg) {
+        return Err(format!("the daemon is running (pid {pid}); stop it first").into());
+    }
+    // Also keeps a daemon for this instance from starting mid-sweep.
+    let _lock = if Path::new(&cfg.runtime_dir).is_dir() {
+        lock_instance(cfg)?
+    } else {
+        None
+    };
+    crate::conflict::check(force)?;
+    let term = Arc::new(AtomicBool::new(false));
+    for sig in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(sig, Arc::clone(&term))?;
+    }
+
+    let (mut cpu_sensors, mut mem_sensors) = resolve_sensors(cfg)?;
+    let mut outputs = open_outputs(cfg)?;
+    let mut fans: Vec<FanStatus> = cfg
+        .fans
+        .iter()
+        .zip(&outputs)
+        .map(|(fan, output)| FanStatus::new(fan, output.as_ref()))
+        .collect();
+    let mut report = Report { lines: Vec::new() };
+    let mut temps: [Vec<f64>; 2] = [Vec::new(), Vec::new()];
+    let mut sensor_errors = Vec::new();
+
+    let mut steps: Vec<i32> = [cfg.min_duty, 50, cfg.max_duty]
+        .iter()
+        .map(|d| clamp_duty(*d, cfg.min_duty, cfg.max_duty))
+        .collect();
+    steps.dedup();
+
+    for (output, fan) in outputs.iter_mut().zip(&mut fans) {
+        let _ = write_duty(output.as_mut(), fan, cfg.failsafe_duty, cfg);
+    }
+
+    'fans: for i in 0..outputs.len() {
+        let fan_cfg = &cfg.fans[i];
+        println!("{} ({}):", fan_cfg.name, outputs[i].describe());
+        let mut rpms = Vec::new();
+        for &duty in &steps {
+            if let Err(e) = write_duty(outputs[i].as_mut(), &mut fans[i], duty, cfg) {
+                report.add(
+                    Verdict::Fail,
+                    format!("{}: write {duty}%: {e}", fan_cfg.name),
+                );
+                continue 'fans;
+            }
+            for _ in 0..SETTLE_SEC {
+                if term.load(Ordering::Relaxed) {
+                    break 'fans;
+                }
+                thread::sleep(Duration::from_secs(1));
+                for (zone, sources) in [&mut cpu_sensors, &mut mem_sensors].into_iter().enumerate()
+                {
+                    match crate::sensor::max_temp(sources) {
+                        Ok(t) => temps[zone].push(t),
+                        Err(e) => sensor_errors.push(e.to_string()),
+                    }
+                }
+            }
+            let rpm = fan_cfg.rpm_path.as_deref().and_then(read_rpm);
+            match rpm {
+                Some(rpm) => println!("    {duty:3}% -> {rpm} rpm"),
+                None => println!("    {duty:3}%"),
+            }
+            rpms.push(rpm);
+        }
+        let _ = write_duty(outputs[i].as_mut(), &mut fans[i], cfg.failsafe_duty, cfg);
+
+        let fan = &fans[i];
+        if fan.readback_mismatches == 0 {
+            let how = if fan.readback.is_some() {
+                "read back as written"
+            } else {
+                "written (no read-back on this output)"
+            };
+            report.add(Verdict::Pass, format!("{}: every duty {how}", fan.name));
+        } else {
+            report.add(
+                Verdict::Fail,
+                format!(
+                    "{}: {} read-back mismatch(es), last: {}",
+                    fan.name,
+                    fan.readback_mismatches,
+                    fan.last_mismatch.as_deref().unwrap_or("-")
+                ),
+            );
+        }
+
+        match (fan_cfg.rpm_path.as_deref(), rpms.first(), rpms.last()) {
+            (None, _, _) => report.add(
+                Verdict::Skip,
+                format!("{}: no rpm_path, tach not checked", fan.name),
+            ),
+            (Some(path), _, _) if rpms.iter().any(Option::is_none) => report.add(
+                Verdict::Fail,
+                format!("{}: cannot read tach {path}", fan.name),
+            ),
+            (Some(_), Some(Some(low)), Some(Some(high))) => {
+                if *high == 0 {
+                    report.add(
+                        Verdict::Fail,
+                        format!("{}: not spinning at {}%", fan.name, steps[steps.len() - 1]),
+                    );
+                } else if steps[0] > 0 && *low == 0 {
+                    report.add(
+                        Verdict::Fail,
+                        format!("{}: stalls at {}%", fan.name, steps[0]),
+                    );
+                } else if f64::from(*high) < f64::from(*low) * 1.1 {
+                    report.add(
+                        Verdict::Fail,
+                        format!("{}: rpm does not follow duty ({low} -> {high})", fan.name),
+                    );
+                } else {
+                    report.add(
+                        Verdict::Pass,
+                        format!("{}: rpm follows duty ({low} -> {high})", fan.name),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    println!("sensors:");
+    if let Some(e) = sensor_errors.first() {
+        report.add(
+            Verdict::Fail,
+            format!("{} failed read(s), first: {e}", sensor_errors.len()),
+        );
+    }
+    for (zone, readings) in ["cpu", "mem"].iter().zip(&temps) {
+        let Some(first) = readings.first() else {
+            report.add(Verdict::Skip, format!("{zone}: no readings"));
+            continue;
+        };
+        let min = readings.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = readings.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let jump = readings
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f64::max);
+        if min < PLAUSIBLE_C.0 || max > PLAUSIBLE_C.1 {
+            report.add(
+                Verdict::Fail,
+                format!("{zone}: {min:.1}..{max:.1}C is not a plausible temperature"),
+            );
+        } else if jump > MAX_JUMP_C {
+            report.add(
+                Verdict::Fail,
+                format!("{zone}: jumped {jump:.1}C between two readings"),
+            );
+        } else if readings.iter().all(|t| t == first) {
+            report.add(
+                Verdict::Warn,
+                format!(
+                    "{zone}: stuck at {first:.1}C for {} readings",
+                    readings.len()
+                ),
+            );
+        } else {
+            report.add(Verdict::Pass, format!("{zone}: {min:.1}..{max:.1}C"));
+        }
+    }
+
+    for (output, fan) in outputs.iter_mut().zip(&fans) {
+        if let Err(e) = output.release() {
+            log_at!(Error, "{}: release failed: {e}", fan.name);
+        }
+    }
+    if term.load(Ordering::Relaxed) {
+        return Err("interrupted".into());
+    }
+    match report.failures() {
+        0 => {
+            println!("selftest passed");
+            Ok(())
+        }
+        n => Err(format!("selftest failed ({n} check(s))").into()),
+    }
+}
@@ -0,0 +1,21 @@
+/// Something that yields one temperature for a zone. A zone reads all of
+/// its sources each cycle and uses the hottest.
+pub trait SensorSource {
+    /// Current temperature in °C.
+    fn read(&mut self) -> Result<f64, Box<dyn std::error::Error>>;
+
+    fn describe(&self) -> String;
+}
+
+/// The hottest reading among `sources`. Any source failing fails the zone,
+/// the same as a hwmon read error always has.
+pub fn max_temp(sources: &mut [Box<dyn SensorSource>]) -> Result<f64, Box<dyn std::error::Error>> {
+    let mut max: Option<f64> = None;
+    for source in sources.iter_mut() {
+        let t = source
+            .read()
+            .map_err(|e| format!("{}: {e}", source.describe()))?;
+        max = Some(max.map_or(t, |m| m.max(t)));
+    }
+    max.ok_or_else(|| "zone has no sensors".into())
+}
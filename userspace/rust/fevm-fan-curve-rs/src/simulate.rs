@@ -0,0 +1,161 @@
+use crate::fan::FanOutput;
+use crate::EIO;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+/// Zones the `--simulate-*` flags can act on.
+const ZONES: [&str; 3] = ["cpu", "mem", "battery"];
+
+/// Faults injected into the running loop by the `--simulate-*` flags, so
+/// the failsafe, escalation and alert paths can be exercised without
+/// heating the machine or pulling cables.
+#[derive(Debug, Default)]
+pub struct Simulate {
+    /// Zone temperatures that replace what the sensors read.
+    temps: BTreeMap<String, f64>,
+    /// Zones whose sensor reads fail.
+    failing_zones: BTreeSet<String>,
+    /// Fans whose writes fail.
+    failing_fans: BTreeSet<String>,
+}
+
+fn check_zone(flag: &str, zone: &str) -> Result<(), String> {
+    if ZONES.contains(&zone) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{flag}: zone must be cpu, mem or battery, got {zone}"
+        ))
+    }
+}
+
+impl Simulate {
+    /// `--simulate-temp ZONE=CELSIUS`.
+    pub fn add_temp(&mut self, arg: &str) -> Result<(), String> {
+        let (zone, t) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("--simulate-temp: expected ZONE=CELSIUS, got {arg}"))?;
+        check_zone("--simulate-temp", zone)?;
+        let t: f64 = t
+            .parse()
+            .map_err(|_| format!("--simulate-temp: not a temperature: {t}"))?;
+        self.temps.insert(zone.to_string(), t);
+        Ok(())
+    }
+
+    /// `--simulate-sensor-failure ZONE`.
+    pub fn add_sensor_failure(&mut self, zone: &str) -> Result<(), String> {
+        check_zone("--simulate-sensor-failure", zone)?;
+        self.failing_zones.insert(zone.to_string());
+        Ok(())
+    }
+
+    /// `--simulate-write-error FAN`; the name is checked against the
+    /// config by `check_fans`.
+    pub fn add_write_error(&mut self, fan: &str) {
+        self.failing_fans.insert(fan.to_string());
+    }
+
+    pub fn check_fans(&self, fans: &[String]) -> Result<(), String> {
+        match self.failing_fans.iter().find(|f| !fans.contains(f)) {
+            Some(fan) => Err(format!("--simulate-write-error: no fan named {fan}")),
+            None => Ok(()),
+        }
+    }
+
+    /// What is being simulated, for the startup warning; None when nothing.
+    pub fn describe(&self) -> Option<String> {
+        let mut parts: Vec<String> = self
+            .temps
+            .iter()
+            .map(|(zone, t)| format!("{zone} at {t}C"))
+            .collect();
+        parts.extend(
+            self.failing_zones
+                .iter()
+                .map(|zone| format!("{zone} sensor failure")),
+        );
+        parts.extend(
+            self.failing_fans
+                .iter()
+                .map(|fan| format!("{fan} write errors")),
+        );
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+
+    /// Whether `zone` has anything to read even without sensors of its own.
+    pub fn covers(&self, zone: &str) -> bool {
+        self.temps.contains_key(zone) || self.failing_zones.contains(zone)
+    }
+
+    /// `read`, the zone's real reading, with any simulated fault applied:
+    /// a failure wins over a temperature, which replaces the reading.
+    pub fn reading(
+        &self,
+        zone: &str,
+        read: impl FnOnce() -> Result<f64, Box<dyn std::error::Error>>,
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        if self.failing_zones.contains(zone) {
+            return Err(format!("{zone}: simulated sensor failure").into());
+        }
+        match self.temps.get(zone) {
+            Some(t) => Ok(*t),
+            None => read(),
+        }
+    }
+
+    /// Wraps the outputs of fans with `--simulate-write-error`.
+    pub fn wrap_outputs(
+        &self,
+        names: &[String],
+        outputs: Vec<Box<dyn FanOutput>>,
+    ) -> Vec<Box<dyn FanOutput>> {
+        outputs
+            .into_iter()
+            .zip(names)
+            .map(|(output, name)| {
+                if self.failing_fans.contains(name) {
+                    Box::new(FailingWrites { inner: output }) as Box<dyn FanOutput>
+                } else {
+                    output
+                }
+            })
+            .collect()
+    }
+}
+
+/// An output whose writes fail with EIO, as a flaky EC does, so the write
+/// retries run before the cycle fails.
+struct FailingWrites {
+    inner: Box<dyn FanOutput>,
+}
+
+impl FanOutput for FailingWrites {
+    fn init(&mut self) -> io::Result<()> {
+        self.inner.init()
+    }
+
+    fn write(&mut self, _duty: i32) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(EIO))
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        self.inner.read()
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        self.inner.release()
+    }
+
+    fn force_manual(&mut self) -> io::Result<bool> {
+        self.inner.force_manual()
+    }
+
+    fn set_auto(&mut self) -> io::Result<bool> {
+        self.inner.set_auto()
+    }
+
+    fn describe(&self) -> String {
+        format!("{} (simulated write errors)", self.inner.describe())
+    }
+}
@@ -0,0 +1,121 @@
+use crate::history::Sample;
+
+/// How often rows older than the retention window are deleted.
+#[cfg(feature = "sqlite")]
+const PRUNE_EVERY_SEC: f64 = 3600.0;
+
+/// Optional sample/event log in a local SQLite database:
+///
+/// - `temps(unix, zone, temp_c)`
+/// - `duties(unix, fan, duty)`
+/// - `events(unix, kind, message)`: start/stop and mode changes
+///
+/// Only available when built with `--features sqlite`.
+#[cfg(feature = "sqlite")]
+pub struct SqliteLog {
+    conn: rusqlite::Connection,
+    zones: Vec<String>,
+    fans: Vec<String>,
+    keep_sec: f64,
+    last_prune: f64,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteLog {
+    pub fn open(
+        path: &str,
+        zones: Vec<String>,
+        fans: Vec<String>,
+        keep_days: f64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS temps (unix REAL NOT NULL, zone TEXT NOT NULL, \
+                 temp_c REAL NOT NULL);
+             CREATE INDEX IF NOT EXISTS temps_unix ON temps (unix);
+             CREATE TABLE IF NOT EXISTS duties (unix REAL NOT NULL, fan TEXT NOT NULL, \
+                 duty INTEGER NOT NULL);
+             CREATE INDEX IF NOT EXISTS duties_unix ON duties (unix);
+             CREATE TABLE IF NOT EXISTS events (unix REAL NOT NULL, kind TEXT NOT NULL, \
+                 message TEXT);
+             CREATE INDEX IF NOT EXISTS events_unix ON events (unix);",
+        )?;
+        Ok(Self {
+            conn,
+            zones,
+            fans,
+            keep_sec: keep_days * 86_400.0,
+            last_prune: 0.0,
+        })
+    }
+
+    pub fn sample(&mut self, sample: &Sample) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut temp = tx.prepare_cached("INSERT INTO temps VALUES (?1, ?2, ?3)")?;
+            for (zone, t) in self.zones.iter().zip(&sample.temps) {
+                if let Some(t) = t {
+                    temp.execute(rusqlite::params![sample.unix, zone, t])?;
+                }
+            }
+            let mut duty = tx.prepare_cached("INSERT INTO duties VALUES (?1, ?2, ?3)")?;
+            for (fan, d) in self.fans.iter().zip(&sample.duties) {
+                if let Some(d) = d {
+                    duty.execute(rusqlite::params![sample.unix, fan, d])?;
+                }
+            }
+        }
+        if sample.unix - self.last_prune >= PRUNE_EVERY_SEC {
+            let cutoff = sample.unix - self.keep_sec;
+            for table in ["temps", "duties", "events"] {
+                tx.execute(&format!("DELETE FROM {table} WHERE unix < ?1"), [cutoff])?;
+            }
+            self.last_prune = sample.unix;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn event(
+        &mut self,
+        unix: f64,
+        kind: &str,
+        message: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn.execute(
+            "INSERT INTO events VALUES (?1, ?2, ?3)",
+            rusqlite::params![unix, kind, message],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub struct SqliteLog;
+
+#[cfg(not(feature = "sqlite"))]
+impl SqliteLog {
+    pub fn open(
+        _path: &str,
+        _zones: Vec<String>,
+        _fans: Vec<String>,
+        _keep_days: f64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Err("built without the sqlite feature".into())
+    }
+
+    pub fn sample(&mut self, _sample: &Sample) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    pub fn event(
+        &mut self,
+        _unix: f64,
+        _kind: &str,
+        _message: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
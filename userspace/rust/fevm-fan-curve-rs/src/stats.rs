@@ -0,0 +1,200 @@
+use crate::history::Sample;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// Duty histogram buckets: 0-9%, 10-19%, ..., 90-100%.
+const BUCKETS: usize = 10;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Keyed by UTC date, `YYYY-MM-DD`.
+    #[serde(default)]
+    pub days: BTreeMap<String, Day>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Day {
+    pub seconds: f64,
+    pub failsafe_count: u64,
+    pub failsafe_seconds: f64,
+    #[serde(default)]
+    pub zones: BTreeMap<String, ZoneStats>,
+    #[serde(default)]
+    pub fans: BTreeMap<String, FanStats>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ZoneStats {
+    pub max_c: f64,
+    /// Seconds spent at or above each threshold, keyed by the threshold.
+    #[serde(default)]
+    pub above: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FanStats {
+    /// Seconds spent in each duty bucket.
+    pub histogram: Vec<f64>,
+}
+
+impl Stats {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match fs::read_to_string(path) {
+            Ok(raw) => Ok(toml::from_str(&raw)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = format!("{path}.tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Accounts `dt` seconds to the day `sample` falls on. `entered_failsafe`
+    /// marks the first cycle of a failsafe episode.
+    pub fn record(
+        &mut self,
+        sample: &Sample,
+        dt: f64,
+        entered_failsafe: bool,
+        zones: &[String],
+        fans: &[String],
+        thresholds: &[f64],
+    ) {
+        let day = self.days.entry(utc_date(sample.unix as u64)).or_default();
+        day.seconds += dt;
+        if sample.failsafe {
+            day.failsafe_seconds += dt;
+        }
+        if entered_failsafe {
+            day.failsafe_count += 1;
+        }
+
+        for (name, temp) in zones.iter().zip(&sample.temps) {
+            let Some(temp) = *temp else { continue };
+            let first = !day.zones.contains_key(name);
+            let zone = day.zones.entry(name.clone()).or_default();
+            zone.max_c = if first { temp } else { zone.max_c.max(temp) };
+            for t in thresholds {
+                let secs = zone.above.entry(t.to_string()).or_insert(0.0);
+                if temp >= *t {
+                    *secs += dt;
+                }
+            }
+        }
+
+        for (name, duty) in fans.iter().zip(&sample.duties) {
+            let Some(duty) = *duty else { continue };
+            let fan = day.fans.entry(name.clone()).or_default();
+            fan.histogram.resize(BUCKETS, 0.0);
+            let bucket = (duty.clamp(0, 100) as usize / 10).min(BUCKETS - 1);
+            fan.histogram[bucket] += dt;
+        }
+    }
+
+    pub fn prune(&mut self, keep_days: usize) {
+        while self.days.len() > keep_days {
+            let oldest = self.days.keys().next().cloned().unwrap();
+            self.days.remove(&oldest);
+        }
+    }
+
+    /// Sum of the last `n` days in the file.
+    pub fn merged(&self, n: usize) -> Day {
+        let mut out = Day::default();
+        for day in self.days.values().rev().take(n) {
+            out.seconds += day.seconds;
+            out.failsafe_count += day.failsafe_count;
+            out.failsafe_seconds += day.failsafe_seconds;
+            for (name, z) in &day.zones {
+                let acc = out.zones.entry(name.clone()).or_default();
+                acc.max_c = acc.max_c.max(z.max_c);
+                for (t, secs) in &z.above {
+                    *acc.above.entry(t.clone()).or_insert(0.0) += secs;
+                }
+            }
+            for (name, f) in &day.fans {
+                let acc = out.fans.entry(name.clone()).or_default();
+                acc.histogram.resize(BUCKETS, 0.0);
+                for (a, b) in acc.histogram.iter_mut().zip(&f.histogram) {
+                    *a += b;
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian date (Howard Hinnant's
+/// `civil_from_days`).
+pub fn utc_date(unix: u64) -> String {
+    let z = (unix / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+fn hours(secs: f64) -> String {
+    format!("{:.1}h", secs / 3600.0)
+}
+
+fn print_day(label: &str, day: &Day) {
+    println!(
+        "{label}: covered {}, failsafe {} time(s) for {}",
+        hours(day.seconds),
+        day.failsafe_count,
+        hours(day.failsafe_seconds)
+    );
+    for (name, z) in &day.zones {
+        let mut above: Vec<(f64, f64)> = z
+            .above
+            .iter()
+            .filter_map(|(t, secs)| Some((t.parse().ok()?, *secs)))
+            .collect();
+        above.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let above: Vec<String> = above
+            .iter()
+            .map(|(t, secs)| format!(">={t}C {}", hours(*secs)))
+            .collect();
+        println!("  {name:<8} max {:5.1}C  {}", z.max_c, above.join("  "));
+    }
+    for (name, f) in &day.fans {
+        let total: f64 = f.histogram.iter().sum();
+        let buckets: Vec<String> = f
+            .histogram
+            .iter()
+            .map(|secs| {
+                if total > 0.0 {
+                    format!("{:3.0}%", secs * 100.0 / total)
+                } else {
+                    "  -".to_string()
+                }
+            })
+            .collect();
+        println!("  {name:<8} duty 0..100 by tenths: {}", buckets.join(" "));
+    }
+}
+
+pub fn print(stats: &Stats, days: usize) {
+    if stats.days.is_empty() {
+        println!("no statistics recorded yet");
+        return;
+    }
+    let recent: Vec<(&String, &Day)> = stats.days.iter().rev().take(days).collect();
+    for (date, day) in recent.iter().rev() {
+        print_day(date, day);
+    }
+    println!();
+    print_day("last 7 days", &stats.merged(7));
+}
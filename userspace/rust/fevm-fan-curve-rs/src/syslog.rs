@@ -0,0 +1,295 @@
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a send to a remote server may block the control loop.
+const NET_TIMEOUT: Duration = Duration::from_secs(1);
+/// Minimum gap between attempts to reconnect to a TCP server that is down.
+const RECONNECT_SEC: u64 = 30;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Severity {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+}
+
+enum Transport {
+    /// /dev/log, in the short form every local daemon understands.
+    Local(UnixDatagram),
+    Udp(UdpSocket),
+    /// RFC 6587 octet-counted frames; reconnected when the server drops.
+    Tcp {
+        addr: String,
+        stream: Option<TcpStream>,
+        last_attempt: Option<Instant>,
+    },
+}
+
+struct Sink {
+    transport: Transport,
+    facility: u8,
+    app: String,
+    hostname: String,
+    /// Set after a failed send until one succeeds, to report outages once.
+    failing: bool,
+}
+
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+const FACILITIES: [(&str, u8); 12] = [
+    ("user", 1),
+    ("daemon", 3),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+    ("syslog", 5),
+    ("auth", 4),
+];
+
+/// Checks a `general.syslog` target: `local`, `udp://host:port` or
+/// `tcp://host:port`.
+pub fn check_target(target: &str) -> Result<(), String> {
+    if target == "local" {
+        return Ok(());
+    }
+    let addr = target
+        .strip_prefix("udp://")
+        .or_else(|| target.strip_prefix("tcp://"))
+        .ok_or_else(|| format!("general.syslog: {target} is not local, udp:// or tcp://"))?;
+    if addr
+        .rsplit_once(':')
+        .is_none_or(|(_, port)| port.parse::<u16>().is_err())
+    {
+        return Err(format!("general.syslog: {target} needs a host:port"));
+    }
+    Ok(())
+}
+
+pub fn facility_code(name: &str) -> Result<u8, String> {
+    FACILITIES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| format!("general.syslog_facility: unknown facility \"{name}\""))
+}
+
+fn connect_tcp(addr: &str) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, format!("{addr}: no address"));
+    for a in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&a, NET_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(NET_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// Starts copying log lines to syslog. `app` names this daemon in the
+/// messages. A TCP server that is down at start is retried later.
+pub fn init(target: &str, facility: &str, app: &str) -> Result<(), String> {
+    check_target(target)?;
+    let facility = facility_code(facility)?;
+    let mut failing = false;
+    let transport = if target == "local" {
+        let socket = UnixDatagram::unbound().map_err(|e| format!("syslog: {e}"))?;
+        socket
+            .connect("/dev/log")
+            .map_err(|e| format!("syslog: /dev/log: {e}"))?;
+        Transport::Local(socket)
+    } else if let Some(addr) = target.strip_prefix("udp://") {
+        let socket = UdpSocket::bind(if addr.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        })
+        .and_then(|s| s.connect(addr).map(|()| s))
+        .map_err(|e| format!("syslog: {addr}: {e}"))?;
+        Transport::Udp(socket)
+    } else {
+        let addr = target.trim_start_matches("tcp://").to_string();
+        let stream = connect_tcp(&addr)
+            .map_err(|e| {
+                eprintln!("syslog: {addr}: {e}; will retry");
+                failing = true;
+            })
+            .ok();
+        Transport::Tcp {
+            addr,
+            stream,
+            last_attempt: Some(Instant::now()),
+        }
+    };
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .ok()
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "-".to_string());
+    *SINK.lock().unwrap_or_else(|e| e.into_inner()) = Some(Sink {
+        transport,
+        facility,
+        app: app.to_string(),
+        hostname,
+        failing,
+    });
+    Ok(())
+}
+
+/// RFC 3339 UTC timestamp with milliseconds of `now`, since the epoch.
+fn timestamp(now: Duration) -> String {
+    let secs = now.as_secs();
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        crate::stats::utc_date(secs),
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+fn priority(facility: u8, severity: Severity) -> u16 {
+    u16::from(facility) * 8 + severity as u16
+}
+
+/// An RFC 5424 message, without message ID or structured data.
+fn rfc5424(pri: u16, timestamp: &str, hostname: &str, app: &str, pid: u32, msg: &str) -> String {
+    format!("<{pri}>1 {timestamp} {hostname} {app} {pid} - - {msg}")
+}
+
+/// An RFC 6587 octet-counted frame: the length in bytes, a space, `line`.
+fn octet_counted(line: &str) -> String {
+    format!("{} {line}", line.len())
+}
+
+impl Sink {
+    fn send(&mut self, severity: Severity, msg: &str) -> io::Result<()> {
+        let pri = priority(self.facility, severity);
+        let pid = std::process::id();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match &mut self.transport {
+            Transport::Local(socket) => {
+                socket.send(format!("<{pri}>{}[{pid}]: {msg}", self.app).as_bytes())?;
+            }
+            Transport::Udp(socket) => {
+                let line = rfc5424(pri, &timestamp(now), &self.hostname, &self.app, pid, msg);
+                socket.send(line.as_bytes())?;
+            }
+            Transport::Tcp {
+                addr,
+                stream,
+                last_attempt,
+            } => {
+                if stream.is_none() {
+                    if last_attempt.is_some_and(|t| t.elapsed().as_secs() < RECONNECT_SEC) {
+                        return Err(io::Error::other("not connected"));
+                    }
+                    *last_attempt = Some(Instant::now());
+                    *stream = Some(connect_tcp(addr)?);
+                }
+                let line = rfc5424(pri, &timestamp(now), &self.hostname, &self.app, pid, msg);
+                let frame = octet_counted(&line);
+                if let Err(e) = stream
+                    .as_mut()
+                    .map_or(Ok(()), |s| s.write_all(frame.as_bytes()))
+                {
+                    *stream = None;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends one line to syslog if `init` was called. Failures are reported on
+/// stderr only, so a dead log server cannot stall or crash the daemon.
+pub fn send(severity: Severity, msg: &str) {
+    let mut sink = SINK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(sink) = sink.as_mut() {
+        match sink.send(severity, msg) {
+            Ok(()) => sink.failing = false,
+            Err(e) if !sink.failing => {
+                eprintln!("syslog: {e}; dropping messages until it recovers");
+                sink.failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_combines_facility_and_severity() {
+        assert_eq!(
+            priority(facility_code("daemon").unwrap(), Severity::Error),
+            27
+        );
+        assert_eq!(
+            priority(facility_code("local7").unwrap(), Severity::Info),
+            190
+        );
+        assert_eq!(
+            priority(facility_code("user").unwrap(), Severity::Warning),
+            12
+        );
+    }
+
+    #[test]
+    fn timestamp_is_rfc3339_utc_with_millis() {
+        assert_eq!(
+            timestamp(Duration::from_millis(1_700_000_000_123)),
+            "2023-11-14T22:13:20.123Z"
+        );
+        assert_eq!(timestamp(Duration::ZERO), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn rfc5424_message() {
+        assert_eq!(
+            rfc5424(
+                27,
+                "2023-11-14T22:13:20.123Z",
+                "box",
+                "fevm-fan-curve",
+                42,
+                "loop error"
+            ),
+            "<27>1 2023-11-14T22:13:20.123Z box fevm-fan-curve 42 - - loop error"
+        );
+    }
+
+    #[test]
+    fn octet_count_is_in_bytes() {
+        assert_eq!(octet_counted("<30>1 x"), "7 <30>1 x");
+        // "风扇" is 6 bytes in UTF-8.
+        assert_eq!(octet_counted("风扇"), "6 风扇");
+        assert_eq!(octet_counted(""), "0 ");
+    }
+
+    #[test]
+    fn targets_and_facilities() {
+        assert!(check_target("local").is_ok());
+        assert!(check_target("udp://10.0.0.1:514").is_ok());
+        assert!(check_target("tcp://[::1]:6514").is_ok());
+        assert!(check_target("tcp://logs").is_err());
+        assert!(check_target("udp://logs:syslog").is_err());
+        assert!(check_target("/dev/log").is_err());
+        assert!(facility_code("kern").is_err());
+    }
+}
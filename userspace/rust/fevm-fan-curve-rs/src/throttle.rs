@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+const PLATFORM_PROFILE: &str = "/sys/firmware/acpi/platform_profile";
+/// A lowered cap that has not moved for this long is taken as deliberate
+/// (`cpupower frequency-set -u`, TLP): thermal caps move with temperature.
+const STEADY_SEC: f64 = 600.0;
+
+fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// A cpufreq policy and the frequency cap it is expected to run under.
+struct Policy {
+    dir: PathBuf,
+    /// `cpuinfo_max_freq`: what the hardware can do.
+    hardware_max: u64,
+    /// The cap taken as deliberate; anything below it is throttling.
+    baseline: u64,
+    /// The cap last seen and since when, for `STEADY_SEC`.
+    last: u64,
+    last_change: Instant,
+}
+
+impl Policy {
+    /// The lowest of `scaling_max_freq`, `bios_limit` (the firmware's
+    /// _PPC limit, on acpi-cpufreq) and `cpuinfo_max_freq`.
+    fn cap(&self) -> Option<u64> {
+        let scaling = read_u64(&self.dir.join("scaling_max_freq"))?;
+        let bios = read_u64(&self.dir.join("bios_limit")).unwrap_or(u64::MAX);
+        Some(scaling.min(bios).min(self.hardware_max))
+    }
+}
+
+/// What power-profiles-daemon, tuned and friends change when they switch
+/// profile: the ACPI platform profile and each policy's governor and
+/// energy/performance preference.
+fn profile(policies: &[Policy]) -> String {
+    let mut parts = vec![fs::read_to_string(PLATFORM_PROFILE).unwrap_or_default()];
+    for p in policies {
+        for file in ["scaling_governor", "energy_performance_preference"] {
+            parts.push(fs::read_to_string(p.dir.join(file)).unwrap_or_default());
+        }
+    }
+    parts.concat()
+}
+
+/// Watches the kernel's signs that the CPU is being throttled for heat:
+/// the Intel thermal_throttle event counters going up, and a cpufreq
+/// policy's cap (`scaling_max_freq` or `bios_limit`) dropping below its
+/// baseline, as the thermal cpufreq cooling device and firmware _PPC limits
+/// do. The baseline is the cap at start and after every governor or
+/// profile change, so caps a power profile puts in place are not counted;
+/// it follows the cap up to `cpuinfo_max_freq`. AMD CPUs throttle inside
+/// the SMU, where neither sign shows, so there this seldom fires.
+pub struct Throttle {
+    /// Counter files and their last value.
+    counters: Vec<(PathBuf, u64)>,
+    policies: Vec<Policy>,
+    profile: String,
+}
+
+impl Throttle {
+    pub fn open() -> Self {
+        let mut counters = Vec::new();
+        let mut policies = Vec::new();
+        let mut cpus: Vec<PathBuf> = fs::read_dir(CPU_DIR)
+            .map(|d| d.flatten().map(|e| e.path()).collect())
+            .unwrap_or_default();
+        cpus.sort();
+        for dir in &cpus {
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            if name
+                .strip_prefix("cpu")
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+            {
+                for file in ["core_throttle_count", "package_throttle_count"] {
+                    let path = dir.join("thermal_throttle").join(file);
+                    if let Some(v) = read_u64(&path) {
+                        counters.push((path, v));
+                    }
+                }
+            }
+        }
+        let mut dirs: Vec<PathBuf> = fs::read_dir(format!("{CPU_DIR}/cpufreq"))
+            .map(|d| d.flatten().map(|e| e.path()).collect())
+            .unwrap_or_default();
+        dirs.sort();
+        for dir in dirs {
+            let Some(hardware_max) = read_u64(&dir.join("cpuinfo_max_freq")) else {
+                continue;
+            };
+            let mut policy = Policy {
+                dir,
+                hardware_max,
+                baseline: hardware_max,
+                last: hardware_max,
+                last_change: Instant::now(),
+            };
+            if let Some(cap) = policy.cap() {
+                (policy.baseline, policy.last) = (cap, cap);
+                policies.push(policy);
+            }
+        }
+        let profile = profile(&policies);
+        Self {
+            counters,
+            policies,
+            profile,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty() && self.policies.is_empty()
+    }
+
+    /// Whether any sign of throttling appeared since the last call.
+    pub fn poll(&mut self) -> bool {
+        let mut throttled = false;
+        for (path, last) in &mut self.counters {
+            if let Some(v) = read_u64(path) {
+                throttled |= v > *last;
+                *last = v;
+            }
+        }
+        let profile = profile(&self.policies);
+        let rebase = profile != self.profile;
+        self.profile = profile;
+        for policy in &mut self.policies {
+            let Some(cap) = policy.cap() else {
+                continue;
+            };
+            if cap != policy.last {
+                policy.last = cap;
+                policy.last_change = Instant::now();
+            }
+            if rebase
+                || cap > policy.baseline
+                || policy.last_change.elapsed().as_secs_f64() >= STEADY_SEC
+            {
+                policy.baseline = cap;
+            }
+            throttled |= cap < policy.baseline;
+        }
+        throttled
+    }
+}
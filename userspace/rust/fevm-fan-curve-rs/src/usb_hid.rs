@@ -0,0 +1,192 @@
+use crate::fan::FanOutput;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// A USB fan controller that speaks a protocol liquidctl documents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    /// NZXT Smart Device V2 and RGB & Fan Controller: three fan channels.
+    SmartDeviceV2,
+    /// Corsair Commander Pro: six fan channels.
+    CommanderPro,
+}
+
+impl Model {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "smart_device_v2" => Some(Self::SmartDeviceV2),
+            "commander_pro" => Some(Self::CommanderPro),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SmartDeviceV2 => "smart_device_v2",
+            Self::CommanderPro => "commander_pro",
+        }
+    }
+
+    pub fn channels(self) -> u32 {
+        match self {
+            Self::SmartDeviceV2 => 3,
+            Self::CommanderPro => 6,
+        }
+    }
+
+    /// USB vendor and product ids the model enumerates as.
+    fn ids(self) -> &'static [(u16, u16)] {
+        match self {
+            Self::SmartDeviceV2 => &[(0x1e71, 0x2006), (0x1e71, 0x2009), (0x1e71, 0x200e)],
+            Self::CommanderPro => &[(0x1b1c, 0x0c10)],
+        }
+    }
+
+    /// The output report that sets `channel` (1-based) to a fixed duty,
+    /// report id first as hidraw expects.
+    #[cfg_attr(not(feature = "hidapi"), allow(dead_code))]
+    fn duty_report(self, channel: u32, duty: i32) -> Vec<u8> {
+        let duty = duty.clamp(0, 100) as u8;
+        let fan = (channel - 1) as u8;
+        match self {
+            Self::SmartDeviceV2 => {
+                // Channels as a bit mask, then one duty byte per channel.
+                let mut report = vec![0u8; 64];
+                report[..3].copy_from_slice(&[0x62, 0x01, 1 << fan]);
+                report[3 + usize::from(fan)] = duty;
+                report
+            }
+            Self::CommanderPro => {
+                // No report id, then the 0x23 "set fan duty" command.
+                let mut report = vec![0u8; 65];
+                report[1..4].copy_from_slice(&[0x23, fan, duty]);
+                report
+            }
+        }
+    }
+}
+
+/// The /dev/hidrawN node of the first matching controller, found through
+/// sysfs so the permission check works without the hidapi feature.
+pub fn hidraw_node(model: Model, serial: Option<&str>) -> io::Result<PathBuf> {
+    for entry in fs::read_dir("/sys/class/hidraw")?.flatten() {
+        let Ok(uevent) = fs::read_to_string(entry.path().join("device/uevent")) else {
+            continue;
+        };
+        let field = |key: &str| {
+            uevent
+                .lines()
+                .find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+        };
+        // HID_ID=0003:00001E71:00002006 (bus, vendor, product).
+        let ids = field("HID_ID").and_then(|id| {
+            let mut parts = id.split(':').skip(1);
+            let vendor = u32::from_str_radix(parts.next()?, 16).ok()?;
+            let product = u32::from_str_radix(parts.next()?, 16).ok()?;
+            Some((u16::try_from(vendor).ok()?, u16::try_from(product).ok()?))
+        });
+        if ids.is_some_and(|ids| model.ids().contains(&ids))
+            && serial.is_none_or(|s| field("HID_UNIQ") == Some(s))
+        {
+            return Ok(PathBuf::from("/dev").join(entry.file_name()));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no {} USB controller found", model.name()),
+    ))
+}
+
+/// One fan channel of a USB controller, written through hidapi. Only
+/// available when built with `--features hidapi`.
+#[cfg(feature = "hidapi")]
+pub struct UsbHidFan {
+    model: Model,
+    channel: u32,
+    device: hidapi::HidDevice,
+    label: String,
+}
+
+#[cfg(feature = "hidapi")]
+impl UsbHidFan {
+    pub fn open(model: Model, channel: u32, serial: Option<&str>) -> io::Result<Self> {
+        let api = hidapi::HidApi::new().map_err(io::Error::other)?;
+        let info = api
+            .device_list()
+            .find(|d| {
+                model.ids().contains(&(d.vendor_id(), d.product_id()))
+                    && serial.is_none_or(|s| d.serial_number() == Some(s))
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no {} USB controller found", model.name()),
+                )
+            })?;
+        let label = format!(
+            "{} {}",
+            info.product_string().unwrap_or(model.name()),
+            info.serial_number().unwrap_or("")
+        );
+        let device = info.open_device(&api).map_err(io::Error::other)?;
+        Ok(Self {
+            model,
+            channel,
+            device,
+            label: label.trim_end().to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "hidapi")]
+impl FanOutput for UsbHidFan {
+    fn write(&mut self, duty: i32) -> io::Result<()> {
+        self.device
+            .write(&self.model.duty_report(self.channel, duty))
+            .map_err(io::Error::other)?;
+        if self.model == Model::CommanderPro {
+            // Every command is answered; reading the reply keeps them from
+            // piling up in the device.
+            let mut reply = [0u8; 16];
+            self.device
+                .read_timeout(&mut reply, 500)
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    /// These controllers keep the last duty once the host stops talking to
+    /// them and have no automatic mode to hand back to, so the fan is left
+    /// at full speed rather than wherever the curve last put it.
+    fn release(&mut self) -> io::Result<()> {
+        self.write(100)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} fan {}", self.label, self.channel)
+    }
+}
+
+#[cfg(not(feature = "hidapi"))]
+pub struct UsbHidFan;
+
+#[cfg(not(feature = "hidapi"))]
+impl UsbHidFan {
+    pub fn open(_model: Model, _channel: u32, _serial: Option<&str>) -> io::Result<Self> {
+        Err(io::Error::other(
+            "backend \"usb_hid\" needs a build with the hidapi feature",
+        ))
+    }
+}
+
+#[cfg(not(feature = "hidapi"))]
+impl FanOutput for UsbHidFan {
+    fn write(&mut self, _duty: i32) -> io::Result<()> {
+        Err(io::Error::other("built without the hidapi feature"))
+    }
+
+    fn describe(&self) -> String {
+        "usb_hid".to_string()
+    }
+}
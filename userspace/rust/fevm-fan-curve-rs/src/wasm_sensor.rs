@@ -0,0 +1,246 @@
+use crate::sensor::SensorSource;
+
+/// Instruction budget for one call into a plugin, so a plugin stuck in a
+/// loop fails its reading instead of hanging the control loop.
+#[cfg(feature = "wasm")]
+const FUEL_PER_CALL: u64 = 10_000_000;
+
+/// A sensor implemented by a WebAssembly module. The plugin ABI:
+///
+/// Exports
+/// - `memory`
+/// - `read() -> f64`: current temperature in °C; NaN means no reading
+/// - `init(ptr: i32, len: i32) -> i32` (optional): receives the `config`
+///   string from the TOML; non-zero fails loading
+/// - `alloc(len: i32) -> i32` (required with `init`): where to put it
+///
+/// Imports, all from `env` and all optional
+/// - `log(ptr: i32, len: i32)`: one line to the daemon's log
+/// - `read_file(path_ptr, path_len, buf_ptr, buf_cap: i32) -> i32`: reads
+///   up to `buf_cap` bytes of a file, returns the length or -1
+/// - `write_file(path_ptr, path_len, data_ptr, data_len: i32) -> i32`:
+///   returns 0 or -1
+///
+/// File access is limited to the plugin's `allow_paths`. Only available
+/// when built with `--features wasm`.
+#[cfg(feature = "wasm")]
+pub struct WasmSensor {
+    name: String,
+    store: wasmi::Store<Host>,
+    read: wasmi::TypedFunc<(), f64>,
+}
+
+#[cfg(feature = "wasm")]
+struct Host {
+    name: String,
+    allow_paths: Vec<String>,
+}
+
+#[cfg(feature = "wasm")]
+impl Host {
+    /// `path` resolved, with symlinks and `..` followed, when that lies
+    /// under one of `allow_paths` (resolved the same way). A file that does
+    /// not exist yet, for writing, is resolved through its directory.
+    fn allowed(&self, path: &str) -> Option<std::path::PathBuf> {
+        let path = std::path::Path::new(path);
+        let resolved = match std::fs::canonicalize(path) {
+            Ok(p) => p,
+            Err(_) => {
+                let name = path.file_name()?;
+                std::fs::canonicalize(path.parent()?).ok()?.join(name)
+            }
+        };
+        self.allow_paths
+            .iter()
+            .filter_map(|p| std::fs::canonicalize(p).ok())
+            .any(|p| resolved.starts_with(p))
+            .then_some(resolved)
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn guest_bytes(caller: &wasmi::Caller<'_, Host>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let mut buf = vec![0u8; usize::try_from(len).ok()?];
+    memory
+        .read(caller, usize::try_from(ptr).ok()?, &mut buf)
+        .ok()?;
+    Some(buf)
+}
+
+#[cfg(feature = "wasm")]
+fn guest_str(caller: &wasmi::Caller<'_, Host>, ptr: i32, len: i32) -> Option<String> {
+    String::from_utf8(guest_bytes(caller, ptr, len)?).ok()
+}
+
+#[cfg(feature = "wasm")]
+impl WasmSensor {
+    pub fn load(
+        name: &str,
+        path: &str,
+        config: &str,
+        allow_paths: Vec<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut wasm_config = wasmi::Config::default();
+        wasm_config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&wasm_config);
+        let bytes = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        let module = wasmi::Module::new(&engine, &bytes).map_err(|e| format!("{path}: {e}"))?;
+        let mut store = wasmi::Store::new(
+            &engine,
+            Host {
+                name: name.to_string(),
+                allow_paths,
+            },
+        );
+
+        let mut linker = wasmi::Linker::<Host>::new(&engine);
+        linker.func_wrap(
+            "env",
+            "log",
+            |caller: wasmi::Caller<'_, Host>, ptr: i32, len: i32| {
+                if let Some(msg) = guest_str(&caller, ptr, len) {
+                    log_at!(Info, "{}: {msg}", caller.data().name);
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "read_file",
+            |mut caller: wasmi::Caller<'_, Host>,
+             path_ptr: i32,
+             path_len: i32,
+             buf_ptr: i32,
+             buf_cap: i32|
+             -> i32 {
+                let Some(path) = guest_str(&caller, path_ptr, path_len) else {
+                    return -1;
+                };
+                let Some(resolved) = caller.data().allowed(&path) else {
+                    log_at!(Warning, "{}: read of {path} denied", caller.data().name);
+                    return -1;
+                };
+                let Ok(mut data) = std::fs::read(resolved) else {
+                    return -1;
+                };
+                data.truncate(usize::try_from(buf_cap).unwrap_or(0));
+                let Some(memory) = caller
+                    .get_export("memory")
+                    .and_then(wasmi::Extern::into_memory)
+                else {
+                    return -1;
+                };
+                match memory.write(&mut caller, buf_ptr as usize, &data) {
+                    Ok(()) => data.len() as i32,
+                    Err(_) => -1,
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "write_file",
+            |caller: wasmi::Caller<'_, Host>,
+             path_ptr: i32,
+             path_len: i32,
+             data_ptr: i32,
+             data_len: i32|
+             -> i32 {
+                let (Some(path), Some(data)) = (
+                    guest_str(&caller, path_ptr, path_len),
+                    guest_bytes(&caller, data_ptr, data_len),
+                ) else {
+                    return -1;
+                };
+                let Some(resolved) = caller.data().allowed(&path) else {
+                    log_at!(Warning, "{}: write to {path} denied", caller.data().name);
+                    return -1;
+                };
+                match std::fs::write(resolved, data) {
+                    Ok(()) => 0,
+                    Err(_) => -1,
+                }
+            },
+        )?;
+
+        store.set_fuel(FUEL_PER_CALL).map_err(|e| e.to_string())?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| format!("{path}: {e}"))?;
+
+        if let Ok(init) = instance.get_typed_func::<(i32, i32), i32>(&store, "init") {
+            let (ptr, len) = if config.is_empty() {
+                (0, 0)
+            } else {
+                let alloc = instance
+                    .get_typed_func::<i32, i32>(&store, "alloc")
+                    .map_err(|e| format!("{path}: alloc: {e}"))?;
+                let len = config.len() as i32;
+                let ptr = alloc.call(&mut store, len)?;
+                let memory = instance
+                    .get_memory(&store, "memory")
+                    .ok_or_else(|| format!("{path}: no exported memory"))?;
+                memory
+                    .write(&mut store, ptr as usize, config.as_bytes())
+                    .map_err(|e| format!("{path}: {e}"))?;
+                (ptr, len)
+            };
+            let rc = init.call(&mut store, (ptr, len))?;
+            if rc != 0 {
+                return Err(format!("{path}: init returned {rc}").into());
+            }
+        }
+        let read = instance
+            .get_typed_func::<(), f64>(&store, "read")
+            .map_err(|e| format!("{path}: read: {e}"))?;
+        Ok(Self {
+            name: name.to_string(),
+            store,
+            read,
+        })
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl SensorSource for WasmSensor {
+    fn read(&mut self) -> Result<f64, Box<dyn std::error::Error>> {
+        self.store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| e.to_string())?;
+        let t = self.read.call(&mut self.store, ())?;
+        if !t.is_finite() {
+            return Err("plugin has no reading".into());
+        }
+        Ok(t)
+    }
+
+    fn describe(&self) -> String {
+        format!("wasm:{}", self.name)
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+pub struct WasmSensor;
+
+#[cfg(not(feature = "wasm"))]
+impl WasmSensor {
+    pub fn load(
+        _name: &str,
+        _path: &str,
+        _config: &str,
+        _allow_paths: Vec<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Err("sensors.plugins needs a build with the wasm feature".into())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+impl SensorSource for WasmSensor {
+    fn read(&mut self) -> Result<f64, Box<dyn std::error::Error>> {
+        Err("built without the wasm feature".into())
+    }
+
+    fn describe(&self) -> String {
+        "wasm".to_string()
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The kernel watchdog, armed while this is alive. If the control loop
+/// hangs and stops petting it, the machine reboots into firmware fan
+/// control. Dropping it (any orderly exit, including an error return or a
+/// panic) writes the magic 'V' first, so only a hang or SIGKILL reboots.
+pub struct Watchdog {
+    path: String,
+    file: File,
+}
+
+impl Watchdog {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).open(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            file,
+        })
+    }
+
+    pub fn pet(&mut self) -> io::Result<()> {
+        self.file.write_all(b"\0")?;
+        self.file.flush()
+    }
+
+    /// The driver's timeout and nowayout setting, when sysfs has them.
+    pub fn info(path: &str) -> (Option<f64>, bool) {
+        let name = Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // /dev/watchdog is the legacy name of watchdog0.
+        let name = if name == "watchdog" {
+            "watchdog0".to_string()
+        } else {
+            name
+        };
+        let dir = Path::new("/sys/class/watchdog").join(name);
+        let read = |f: &str| fs::read_to_string(dir.join(f)).ok();
+        let timeout = read("timeout").and_then(|v| v.trim().parse().ok());
+        let nowayout = read("nowayout").is_some_and(|v| v.trim() == "1");
+        (timeout, nowayout)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.write_all(b"V").and_then(|()| self.file.flush()) {
+            log_at!(Error, "{}: cannot disarm: {e}", self.path);
+        }
+    }
+}